@@ -3,12 +3,11 @@ use std::io::Write;
 use std::{
     ffi::OsString,
     fs::{File, ReadDir},
-    path::PathBuf,
 };
 use tempfile::tempfile;
 
-use crate::git_commands::GIT_ROOT;
-use crate::CONFIG_ROOT;
+use crate::git_commands::CONFIG_LOCATION;
+use crate::LOG_FILE;
 
 pub fn backup_files(config_files: ReadDir) -> anyhow::Result<Vec<(OsString, File, String)>> {
     let mut backups = Vec::new();
@@ -16,6 +15,11 @@ pub fn backup_files(config_files: ReadDir) -> anyhow::Result<Vec<(OsString, File
     for entry in config_files {
         let config_file = entry?;
 
+        // The log is not committed so it does not need to be backed up
+        if config_file.file_name() == LOG_FILE {
+            continue;
+        }
+
         let path = config_file.path();
         let contents = read_to_string(&path)?;
 
@@ -30,7 +34,7 @@ pub fn backup_files(config_files: ReadDir) -> anyhow::Result<Vec<(OsString, File
     Ok(backups)
 }
 pub fn restore_backup(file_name: &OsString, contents: &str) -> anyhow::Result<()> {
-    let path = GIT_ROOT.join(PathBuf::from(CONFIG_ROOT).join(file_name));
+    let path = CONFIG_LOCATION.root.join(file_name);
     let mut file = File::create(&path)?;
 
     write!(file, "{contents}")?;
@@ -0,0 +1,126 @@
+use std::fs;
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::{gen_patch::diff_options, help},
+    fail,
+    flags::{is_valid_flag, Flag},
+    git_commands::{CONFIG_LOCATION, GIT},
+    run_log::read_run_log,
+    success,
+    types::{CommandArgs, Configuration},
+    APP_NAME, CONFIG_FILE, CONFIG_ROOT, LOG_FILE,
+};
+
+use super::{
+    gen_patch::{GEN_PATCH_DIFF_CONTEXT_FLAG, GEN_PATCH_DIFF_OPTION_FLAG},
+    help::{CONFIG_FLAG, CONFIG_ROOT_FLAG, HELP_FLAG, REDACT_FLAG, VERSION_FLAG},
+};
+
+pub static EXPORT_FORMAT_FLAG: Flag<'static> = Flag {
+    short: "-f=",
+    long: "--format=",
+    description: "What to export the fork as. The only supported format is `patch-series`, which is the default",
+};
+
+pub static EXPORT_FLAGS: &[&Flag<'static>; 8] = &[
+    &EXPORT_FORMAT_FLAG,
+    &GEN_PATCH_DIFF_OPTION_FLAG,
+    &GEN_PATCH_DIFF_CONTEXT_FLAG,
+    &CONFIG_FLAG,
+    &CONFIG_ROOT_FLAG,
+    &HELP_FLAG,
+    &VERSION_FLAG,
+    &REDACT_FLAG,
+];
+
+pub fn export(args: &CommandArgs) -> anyhow::Result<()> {
+    if let Some(arg) = args
+        .iter()
+        .find(|arg| arg.starts_with('-') && !is_valid_flag(arg, EXPORT_FLAGS))
+    {
+        fail!("Invalid flag: {arg}");
+        let _ = help(Some("export"));
+        std::process::exit(1);
+    }
+
+    if let Some(format) = args
+        .iter()
+        .find_map(|arg| EXPORT_FORMAT_FLAG.extract_from_arg(arg))
+        .filter(|format| format != "patch-series")
+    {
+        return Err(anyhow!(
+            "Unsupported format {format}, the only supported format is patch-series"
+        ));
+    }
+
+    let Some(output_directory) = args.iter().find(|arg| !arg.starts_with('-')) else {
+        fail!("You haven't specified a directory to export to");
+        return help(Some("export"));
+    };
+
+    let config_raw = fs::read_to_string(&CONFIG_LOCATION.file).map_err(|err| {
+        anyhow!(
+            "Could not read configuration file at {}\n{err}",
+            CONFIG_LOCATION.file.to_string_lossy()
+        )
+    })?;
+
+    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+        anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+    })?;
+
+    let last_run = read_run_log()?.pop().ok_or_else(|| {
+        anyhow!("No runs have been recorded in {CONFIG_ROOT}/{LOG_FILE} yet, so there is nothing to export")
+    })?;
+
+    if GIT(&["rev-parse", &config.local_branch])? != last_run.sha {
+        return Err(anyhow!(
+            "{} has changed since the last run, so it may contain commits which {APP_NAME} did not create",
+            config.local_branch.cyan()
+        ));
+    }
+
+    // every pull request is squashed into a single commit, so the fork's history is linear on top of upstream
+    let upstream = match (last_run.pull_requests.first(), &last_run.base) {
+        (Some(first_pull_request), _) => format!("{}^", first_pull_request.commit),
+        (None, Some(base)) => base.clone(),
+        (None, None) => {
+            return Err(anyhow!(
+                "The last run was made by an older version of {APP_NAME}, so it can't be exported"
+            ))
+        }
+    };
+
+    // the configuration files are only needed by patchy
+    let last_commit_message = GIT(&["log", "-1", "--format=%s", &last_run.sha])?;
+    let end = if last_commit_message == format!("{APP_NAME}: Restore configuration files") {
+        format!("{}^", last_run.sha)
+    } else {
+        last_run.sha
+    };
+
+    let range = format!("{upstream}..{end}");
+    let diff_options = diff_options(args)?;
+
+    let mut format_patch_args = vec![
+        "format-patch",
+        "--output-directory",
+        output_directory,
+        &range,
+    ];
+    format_patch_args.extend(diff_options.iter().map(String::as_str));
+
+    let patches = GIT(&format_patch_args)?;
+
+    success!(
+        "Exported {} patches to {}, which can be applied on top of upstream with {}",
+        patches.lines().count().to_string().bright_blue(),
+        output_directory.bright_cyan(),
+        format!("git am {output_directory}/*.patch").bright_magenta()
+    );
+
+    Ok(())
+}
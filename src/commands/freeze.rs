@@ -0,0 +1,216 @@
+use std::fs;
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::{help, pr_fetch::ignore_octothorpe, run::parse_if_maybe_hash},
+    fail,
+    git_commands::{
+        add_remote_branch, clean_up_remote, fetch_pull_request, repo_url, request_default_branch,
+        request_pull_request, upstream_remote_alias, CONFIG_LOCATION, GIT,
+    },
+    success,
+    types::{Branch, BranchAndRemote, CommandArgs, Configuration, Remote},
+    utils::with_uuid,
+    CONFIG_FILE, CONFIG_ROOT,
+};
+
+/// Name of the patch which a frozen pull request is turned into
+fn frozen_patch_name(pull_request: &str) -> String {
+    format!("pull-request-{pull_request}")
+}
+
+/// Creates a commit with all changes of the pull request on top of the commit it is based on,
+/// without touching the working tree. Returns the new commit
+fn squash_pull_request(
+    upstream_branch: &str,
+    pull_request_branch: &str,
+    message: &str,
+) -> anyhow::Result<String> {
+    let base = GIT(&["merge-base", upstream_branch, pull_request_branch])?;
+
+    GIT(&[
+        "commit-tree",
+        &format!("{pull_request_branch}^{{tree}}"),
+        "-p",
+        &base,
+        "-m",
+        message,
+    ])
+}
+
+/// Replaces the pull requests in the config with their patches, keeping the formatting and comments of the file
+fn replace_pull_requests_with_patches(
+    config_raw: &str,
+    pull_requests: &[String],
+) -> anyhow::Result<String> {
+    let mut document = config_raw
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|err| {
+            anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+        })?;
+
+    if let Some(config_pull_requests) = document
+        .get_mut("pull-requests")
+        .and_then(|item| item.as_array_mut())
+    {
+        config_pull_requests.retain(|value| {
+            let pull_request = match value.as_inline_table() {
+                Some(table) => table.get("id").and_then(|id| id.as_str()),
+                None => value.as_str(),
+            };
+
+            pull_request.is_none_or(|pull_request| {
+                let (pull_request, _) =
+                    parse_if_maybe_hash(&ignore_octothorpe(pull_request), " @ ");
+                !pull_requests.contains(&pull_request)
+            })
+        });
+    }
+
+    let patches = document
+        .entry("patches")
+        .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("`patches` in {CONFIG_ROOT}/{CONFIG_FILE} is not a list"))?;
+
+    for pull_request in pull_requests {
+        let name = frozen_patch_name(pull_request);
+
+        if !patches.iter().any(|patch| patch.as_str() == Some(&name)) {
+            patches.push(name);
+        }
+    }
+
+    Ok(document.to_string())
+}
+
+pub async fn freeze(args: &CommandArgs) -> anyhow::Result<()> {
+    let pull_requests: Vec<_> = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| ignore_octothorpe(arg))
+        .collect();
+
+    if pull_requests.is_empty() {
+        fail!("You haven't specified any pull requests");
+        return help(Some("freeze"));
+    }
+
+    let config_file_path = &CONFIG_LOCATION.file;
+
+    let config_raw = fs::read_to_string(config_file_path).map_err(|err| {
+        anyhow!(
+            "Could not read configuration file at {}\n{err}",
+            config_file_path.to_string_lossy()
+        )
+    })?;
+
+    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+        anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+    })?;
+
+    let client = reqwest::Client::new();
+
+    let remote_branch = match config.remote_branch {
+        Some(ref remote_branch) => remote_branch.clone(),
+        None => request_default_branch(&config.repo, &client).await?,
+    };
+    let (remote_branch, commit_hash) = parse_if_maybe_hash(&remote_branch, " @ ");
+
+    let upstream = BranchAndRemote {
+        branch: Branch {
+            upstream_branch_name: remote_branch.clone(),
+            local_branch_name: with_uuid(&remote_branch),
+        },
+        remote: Remote {
+            repository_url: repo_url(&config.repo),
+            local_remote_alias: upstream_remote_alias(&config.repo),
+        },
+    };
+
+    // the merge base with upstream is where the changes of the pull request begin
+    add_remote_branch(&upstream, &commit_hash, None)?;
+    let mut frozen_pull_requests = vec![];
+
+    for pull_request in &pull_requests {
+        // freeze the pull request at the same commit that the config uses
+        let commit_hash = config.pull_requests.iter().find_map(|config_pull_request| {
+            let (number, commit_hash) = config_pull_request.number_and_commit();
+            (&number == pull_request)
+                .then(|| commit_hash.or(config_pull_request.up_to().map(String::from)))?
+        });
+
+        let result = async {
+            let response = request_pull_request(&config.repo, pull_request, &client).await?;
+            let (response, info) = fetch_pull_request(
+                response,
+                pull_request,
+                None,
+                &commit_hash,
+                &config.repo,
+                config.fetch_from,
+                None,
+            )?;
+
+            let patch = squash_pull_request(
+                &upstream.branch.local_branch_name,
+                &info.branch.local_branch_name,
+                &format!(
+                    "{} (#{pull_request})\n\nFrozen from {}",
+                    response.title, response.html_url
+                ),
+            )
+            .and_then(|commit| GIT(&["format-patch", "-1", "--stdout", &commit]));
+
+            clean_up_remote(
+                &info.remote.local_remote_alias,
+                &info.branch.local_branch_name,
+            )?;
+
+            let patch_path = CONFIG_LOCATION
+                .root
+                .join(format!("{}.patch", frozen_patch_name(pull_request)));
+
+            fs::write(&patch_path, format!("{}\n", patch?))?;
+
+            anyhow::Ok(patch_path)
+        }
+        .await;
+
+        match result {
+            Ok(patch_path) => {
+                success!(
+                    "Froze pull request {}{} into {}",
+                    "#".bright_blue(),
+                    pull_request.bright_blue(),
+                    patch_path.to_string_lossy().bright_cyan()
+                );
+                frozen_pull_requests.push(pull_request.clone());
+            }
+            Err(err) => fail!("Could not freeze pull request #{pull_request}\n{err}"),
+        }
+    }
+
+    clean_up_remote(
+        &upstream.remote.local_remote_alias,
+        &upstream.branch.local_branch_name,
+    )?;
+
+    if frozen_pull_requests.is_empty() {
+        return Err(anyhow!("No pull requests were frozen"));
+    }
+
+    fs::write(
+        config_file_path,
+        replace_pull_requests_with_patches(&config_raw, &frozen_pull_requests)?,
+    )?;
+
+    success!(
+        "Replaced the frozen pull requests with their patches in {}",
+        config_file_path.to_string_lossy()
+    );
+
+    Ok(())
+}
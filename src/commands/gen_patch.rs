@@ -1,17 +1,22 @@
-use std::fs;
+use std::{fs, sync::Mutex, thread};
+
+use anyhow::anyhow;
+use serde::Deserialize;
 
-use crate::CONFIG_ROOT;
 use crate::{
     commands::help,
     fail,
-    flags::{is_valid_flag, Flag},
-    git_commands::{is_valid_branch_name, GIT, GIT_ROOT},
+    flags::{is_valid_flag, Flag, THREADS},
+    git_commands::{is_valid_branch_name, CONFIG_LOCATION, GIT},
     success,
     types::CommandArgs,
     utils::normalize_commit_msg,
 };
+use crate::{CONFIG_FILE, CONFIG_ROOT};
 
-use super::help::{HELP_FLAG, VERSION_FLAG};
+use super::help::{
+    CONFIG_FLAG, CONFIG_ROOT_FLAG, HELP_FLAG, REDACT_FLAG, THREADS_FLAG, VERSION_FLAG,
+};
 
 pub static GEN_PATCH_NAME_FLAG: Flag<'static> = Flag {
     short: "-n=",
@@ -19,19 +24,150 @@ pub static GEN_PATCH_NAME_FLAG: Flag<'static> = Flag {
     description: "Choose filename for the patch",
 };
 
-pub static GEN_PATCH_FLAGS: &[&Flag<'static>; 3] =
-    &[&GEN_PATCH_NAME_FLAG, &HELP_FLAG, &VERSION_FLAG];
+pub static GEN_PATCH_DIFF_OPTION_FLAG: Flag<'static> = Flag {
+    short: "-d=",
+    long: "--diff-opt=",
+    description: "Pass an option to git when generating the patch, e.g. --diff-opt=--function-context. Can be used multiple times",
+};
+
+pub static GEN_PATCH_DIFF_CONTEXT_FLAG: Flag<'static> = Flag {
+    short: "-u=",
+    long: "--diff-context=",
+    description: "How many lines of context to put around each change, which makes the patch more likely to apply after upstream changes. Defaults to 3",
+};
+
+pub static GEN_PATCH_FLAGS: &[&Flag<'static>; 9] = &[
+    &GEN_PATCH_NAME_FLAG,
+    &GEN_PATCH_DIFF_OPTION_FLAG,
+    &GEN_PATCH_DIFF_CONTEXT_FLAG,
+    &THREADS_FLAG,
+    &CONFIG_FLAG,
+    &CONFIG_ROOT_FLAG,
+    &HELP_FLAG,
+    &VERSION_FLAG,
+    &REDACT_FLAG,
+];
+
+/// Options which only change how the diff looks, so they can't be used to make git do anything unexpected
+static ALLOWED_DIFF_OPTIONS: &[&str] = &[
+    "-W",
+    "--function-context",
+    "-M",
+    "--find-renames",
+    "-C",
+    "--find-copies",
+    "--no-renames",
+    "-w",
+    "--ignore-all-space",
+    "-b",
+    "--ignore-space-change",
+    "--ignore-space-at-eol",
+    "--ignore-blank-lines",
+    "--minimal",
+    "--patience",
+    "--histogram",
+    "--full-index",
+    "--binary",
+];
+
+/// More lines of context than this would mostly put whole files into the patch
+static MAX_DIFF_CONTEXT: usize = 10000;
+
+/// Checks that an option for `git format-patch` is in the allowlist.
+///
+/// Options which accept a value, like `-M50%` or `--find-renames=50%`, are also allowed
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::commands::gen_patch::is_allowed_diff_option;
+///
+/// assert!(is_allowed_diff_option("--function-context"));
+/// assert!(is_allowed_diff_option("-M"));
+/// assert!(is_allowed_diff_option("-M50%"));
+/// assert!(is_allowed_diff_option("--find-renames=50%"));
+/// assert!(!is_allowed_diff_option("--output=/etc/passwd"));
+/// assert!(!is_allowed_diff_option("-o"));
+/// ```
+pub fn is_allowed_diff_option(option: &str) -> bool {
+    ALLOWED_DIFF_OPTIONS.iter().any(|allowed| {
+        option == *allowed
+            || matches!(*allowed, "-M" | "-C") && option.starts_with(allowed)
+            || matches!(*allowed, "--find-renames" | "--find-copies")
+                && option.starts_with(&format!("{allowed}="))
+    })
+}
+
+/// The `diff-options` from the config, if there is one
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct GenPatchConfiguration {
+    #[serde(default)]
+    diff_options: Vec<String>,
+}
+
+/// Options for `git format-patch` from the `diff-options` of the config, the `--diff-opt` flags and `--diff-context`
+pub fn diff_options(args: &CommandArgs) -> anyhow::Result<Vec<String>> {
+    let config = match fs::read_to_string(&CONFIG_LOCATION.file) {
+        Ok(config_raw) => toml::from_str::<GenPatchConfiguration>(&config_raw).map_err(|err| {
+            anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+        })?,
+        Err(_) => GenPatchConfiguration::default(),
+    };
+
+    let mut diff_options: Vec<String> = config
+        .diff_options
+        .into_iter()
+        .chain(
+            args.iter()
+                .filter_map(|arg| GEN_PATCH_DIFF_OPTION_FLAG.extract_from_arg(arg)),
+        )
+        .collect();
+
+    if let Some(option) = diff_options
+        .iter()
+        .find(|option| !is_allowed_diff_option(option))
+    {
+        return Err(anyhow!(
+            "Diff option {option} is not allowed. Allowed options are: {}",
+            ALLOWED_DIFF_OPTIONS.join(", ")
+        ));
+    }
+
+    if let Some(context) = args
+        .iter()
+        .find_map(|arg| GEN_PATCH_DIFF_CONTEXT_FLAG.extract_from_arg(arg))
+    {
+        let context = context
+            .parse::<usize>()
+            .ok()
+            .filter(|context| *context <= MAX_DIFF_CONTEXT)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Invalid value for {}: {context}, expected a number from 0 to {MAX_DIFF_CONTEXT}",
+                    GEN_PATCH_DIFF_CONTEXT_FLAG.long.trim_end_matches('=')
+                )
+            })?;
+
+        diff_options.push(format!("--unified={context}"));
+    }
+
+    Ok(diff_options)
+}
 
 pub fn gen_patch(args: &CommandArgs) -> anyhow::Result<()> {
     if args.is_empty() {
         fail!("You haven't specified any commit hashes");
         help(Some("gen-patch"))?;
     }
+
+    let config_path = &CONFIG_LOCATION.root;
+
+    let diff_options = diff_options(args)?;
+
     let mut args = args.iter().peekable();
     let mut commit_hashes_with_maybe_custom_patch_filenames = vec![];
 
-    let config_path = GIT_ROOT.join(CONFIG_ROOT);
-
     let mut no_more_flags = false;
 
     while let Some(arg) = args.next() {
@@ -83,49 +219,71 @@ pub fn gen_patch(args: &CommandArgs) -> anyhow::Result<()> {
             "Config directory {} does not exist, creating it...",
             config_path.to_string_lossy()
         );
-        fs::create_dir(&config_path)?;
+        fs::create_dir_all(config_path)?;
     }
 
-    for (patch_commit_hash, maybe_custom_patch_name) in
-        commit_hashes_with_maybe_custom_patch_filenames
-    {
-        // 1. if the user provides a custom filename for the patch file, use that
-        // 2. otherwise use the commit message
-        // 3. if all fails use the commit hash
-        let patch_filename = maybe_custom_patch_name.unwrap_or({
-            GIT(&["log", "--format=%B", "--max-count=1", patch_commit_hash])
-                .map(|commit_msg| normalize_commit_msg(&commit_msg))
-                .unwrap_or(patch_commit_hash.to_string())
-        });
+    let generate_patch =
+        |(patch_commit_hash, maybe_custom_patch_name): (&String, Option<String>)| {
+            // 1. if the user provides a custom filename for the patch file, use that
+            // 2. otherwise use the commit message
+            // 3. if all fails use the commit hash
+            let patch_filename = maybe_custom_patch_name.unwrap_or({
+                GIT(&["log", "--format=%B", "--max-count=1", patch_commit_hash])
+                    .map(|commit_msg| normalize_commit_msg(&commit_msg))
+                    .unwrap_or(patch_commit_hash.to_string())
+            });
 
-        let patch_filename = format!("{patch_filename}.patch");
+            let patch_filename = format!("{patch_filename}.patch");
 
-        let patch_file_path = config_path.join(&patch_filename);
+            let patch_file_path = config_path.join(&patch_filename);
 
-        // Paths are UTF-8 encoded. If we cannot convert to UTF-8 that means it is not a valid path
-        let Some(patch_file_path_str) = patch_file_path.as_os_str().to_str() else {
-            fail!("Not a valid path: {patch_file_path:?}");
-            continue;
-        };
+            // Paths are UTF-8 encoded. If we cannot convert to UTF-8 that means it is not a valid path
+            let Some(patch_file_path_str) = patch_file_path.as_os_str().to_str() else {
+                fail!("Not a valid path: {patch_file_path:?}");
+                return;
+            };
 
-        if let Err(err) = GIT(&[
-            "format-patch",
-            "-1",
-            patch_commit_hash,
-            "--output",
-            patch_file_path_str,
-        ]) {
-            fail!(
-                "Could not get patch output for patch {}\n{err}",
-                patch_commit_hash
-            );
-            continue;
+            let mut format_patch_args = vec![
+                "format-patch",
+                "-1",
+                patch_commit_hash,
+                "--output",
+                patch_file_path_str,
+            ];
+            format_patch_args.extend(diff_options.iter().map(String::as_str));
+
+            if let Err(err) = GIT(&format_patch_args) {
+                fail!(
+                    "Could not get patch output for patch {}\n{err}",
+                    patch_commit_hash
+                );
+                return;
+            };
+
+            success!(
+                "Created patch file at {}",
+                patch_file_path.to_string_lossy()
+            )
         };
 
-        success!(
-            "Created patch file at {}",
-            patch_file_path.to_string_lossy()
-        )
+    // Generating patches only reads from the repository, so unlike most git commands it's fine to run in parallel
+    if *THREADS == 1 {
+        commit_hashes_with_maybe_custom_patch_filenames
+            .into_iter()
+            .for_each(generate_patch);
+    } else {
+        let queue = Mutex::new(commit_hashes_with_maybe_custom_patch_filenames.into_iter());
+
+        thread::scope(|scope| {
+            for _ in 0..*THREADS {
+                scope.spawn(|| loop {
+                    let Some(patch) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    generate_patch(patch);
+                });
+            }
+        });
     }
 
     Ok(())
@@ -2,12 +2,22 @@ use colored::Colorize;
 
 use crate::{
     commands::{
-        gen_patch::GEN_PATCH_NAME_FLAG,
+        export::EXPORT_FORMAT_FLAG,
+        gen_patch::{GEN_PATCH_DIFF_CONTEXT_FLAG, GEN_PATCH_DIFF_OPTION_FLAG, GEN_PATCH_NAME_FLAG},
+        list::{LIST_BODY_LINES_FLAG, LIST_INCLUDE_BODY_FLAG},
         pr_fetch::{PR_FETCH_BRANCH_NAME_FLAG, PR_FETCH_CHECKOUT_FLAG, PR_FETCH_REPO_NAME_FLAG},
-        run::RUN_YES_FLAG,
+        run::{
+            RUN_CONCURRENCY_GROUP_FLAG, RUN_CONTINUE_FLAG, RUN_DEADLINE_FLAG, RUN_DEPTH_FLAG,
+            RUN_DRY_RUN_FLAG, RUN_EVENTS_FLAG, RUN_FETCH_DEADLINE_FLAG,
+            RUN_MAX_FETCH_RETRIES_PER_PR_FLAG, RUN_NO_BACKUP_FLAG, RUN_ONLY_PATCHES_FLAG,
+            RUN_OVERRIDE_READONLY_FLAG, RUN_PROFILE_FLAG, RUN_REPORT_FLAG, RUN_REPORT_FORMAT_FLAG,
+            RUN_STEP_SUMMARY_FLAG, RUN_STRICT_FLAG, RUN_UNSHALLOW_FLAG,
+            RUN_VERIFY_CLEAN_AFTER_FLAG, RUN_WATCH_FLAG, RUN_YES_FLAG,
+        },
+        test_patches::TEST_PATCHES_UPSTREAM_REF_FLAG,
     },
     flags::Flag,
-    APP_NAME,
+    APP_NAME, CONFIG_FILE, CONFIG_ROOT, LOG_FILE,
 };
 
 fn format_subcommand(command: &str, description: &str) -> String {
@@ -31,12 +41,54 @@ pub static VERBOSE_FLAG: Flag<'static> = Flag {
     description: "Increased logging information",
 };
 
+pub static THREADS_FLAG: Flag<'static> = Flag {
+    short: "-j=",
+    long: "--threads=",
+    description: "How many pull requests to fetch or patches to generate at the same time. Defaults to the number of logical CPUs, use 1 for sequential work with reproducible output ordering",
+};
+
+pub static CONFIG_FLAG: Flag<'static> = Flag {
+    short: "-C=",
+    long: "--config=",
+    description: "Use this configuration file. Patches are looked up in the same directory",
+};
+
+pub static CONFIG_ROOT_FLAG: Flag<'static> = Flag {
+    short: "-R=",
+    long: "--config-root=",
+    description: "Use this directory for the configuration file and patches, instead of searching for .patchy in the current directory and its parents",
+};
+
+pub static PAGER_FLAG: Flag<'static> = Flag {
+    short: "-P=",
+    long: "--pager=",
+    description: "When to show the output through $PAGER: auto (when the output is a terminal, the default), always or never",
+};
+
+pub static NO_PAGER_FLAG: Flag<'static> = Flag {
+    short: "-N",
+    long: "--no-pager",
+    description: "Do not show the output through $PAGER",
+};
+
 pub static VERSION_FLAG: Flag<'static> = Flag {
     short: "-v",
     long: "--version",
     description: "Get patchy version",
 };
 
+pub static REDACT_FLAG: Flag<'static> = Flag {
+    short: "-X",
+    long: "--redact",
+    description: "Replace repositories, URLs, tokens and generated branch names in the output with placeholders, so that it can be shared in a bug report",
+};
+
+pub static SELFTEST_FLAG: Flag<'static> = Flag {
+    short: "-T",
+    long: "--selftest",
+    description: "Run patchy on a temporary repository, to check that it works in this environment",
+};
+
 pub fn help(command: Option<&str>) -> anyhow::Result<()> {
     let author = "Nikita Revenco ".italic();
     let less_than = "<".bright_black().italic();
@@ -54,6 +106,32 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
     );
     let gen_patch = format_subcommand("gen-patch", "Generate a .patch file from commit hashes");
     let run = format_subcommand("run", &format!("Start {APP_NAME}"));
+    let run_all = format_subcommand(
+        "run-all",
+        &format!("Start {APP_NAME} for every config in some directories"),
+    );
+    let log = format_subcommand("log", "Show the history of runs, newest first");
+    let list = format_subcommand("list", "Show the title of each pull request in the config");
+    let select = format_subcommand(
+        "select",
+        "Pick the pull requests in the config from the open pull requests",
+    );
+    let which = format_subcommand(
+        "which",
+        "Show the merge commit of a pull request from the last run",
+    );
+    let freeze = format_subcommand(
+        "freeze",
+        "Replace pull requests in the config with patches, so they don't need to be fetched",
+    );
+    let export = format_subcommand(
+        "export",
+        "Export the pull requests and patches merged by the last run as a series of patches",
+    );
+    let test_patches = format_subcommand(
+        "test-patches",
+        "Check that the patches apply to a fresh checkout of upstream",
+    );
     let header = format!(
         "  {app_name} {version}
   {author}{less_than}{email}{greater_than}"
@@ -75,6 +153,10 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
 
   Flags:
 
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
     {HELP_FLAG}
 ",
             );
@@ -95,9 +177,352 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
 
   Flags:
 
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
     {HELP_FLAG}
 
     {RUN_YES_FLAG}
+
+    {RUN_ONLY_PATCHES_FLAG}
+
+    {RUN_DRY_RUN_FLAG}
+
+    {RUN_WATCH_FLAG}
+
+    {RUN_OVERRIDE_READONLY_FLAG}
+
+    {RUN_EVENTS_FLAG}
+
+    {RUN_STEP_SUMMARY_FLAG}
+
+    {RUN_REPORT_FLAG}
+
+    {RUN_REPORT_FORMAT_FLAG}
+
+    {RUN_PROFILE_FLAG}
+
+    {RUN_NO_BACKUP_FLAG}
+
+    {RUN_UNSHALLOW_FLAG}
+
+    {RUN_DEPTH_FLAG}
+
+    {RUN_STRICT_FLAG}
+
+    {RUN_CONTINUE_FLAG}
+
+    {RUN_VERIFY_CLEAN_AFTER_FLAG}
+
+    {RUN_CONCURRENCY_GROUP_FLAG}
+
+    {RUN_MAX_FETCH_RETRIES_PER_PR_FLAG}
+
+    {RUN_FETCH_DEADLINE_FLAG}
+
+    {RUN_DEADLINE_FLAG}
+
+    {THREADS_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "run-all") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(&format!(
+                "Find every {CONFIG_ROOT}/{CONFIG_FILE} in the given directories, and run them at the same time. Flags are passed on to each run"
+            ));
+
+            let example_1 = format!(
+                "{} {}
+    {}",
+                "~/forks".bright_green(),
+                "--yes".bright_magenta(),
+                format_description("Update every fork in ~/forks")
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {args} {flags_label}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {RUN_YES_FLAG}
+
+    {THREADS_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "log") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(&format!(
+                "Show the history of runs recorded in {CONFIG_ROOT}/{LOG_FILE}, newest first"
+            ));
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name}
+    {description}
+
+  Flags:
+
+    {PAGER_FLAG}
+
+    {NO_PAGER_FLAG}
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "list") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(&format!(
+                "Show the title of each pull request in {CONFIG_ROOT}/{CONFIG_FILE}"
+            ));
+
+            let example_1 = format!(
+                "{} {}
+    {}",
+                "--include-body".bright_magenta(),
+                "--body-lines=10".bright_magenta(),
+                format_description(
+                    "Also show the first 10 lines of each pull request's description"
+                )
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {flags_label}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {LIST_INCLUDE_BODY_FLAG}
+
+    {LIST_BODY_LINES_FLAG}
+
+    {THREADS_FLAG}
+
+    {PAGER_FLAG}
+
+    {NO_PAGER_FLAG}
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "select") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(
+                "Show the open pull requests of upstream with the ones in the config checked, and write the selected ones to the config",
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name}
+    {description}
+
+  Flags:
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "which") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description =
+                format_description("Show the commit which merged a pull request in the last run");
+
+            let example_1 = format!(
+                "{}
+    {}",
+                "12111".bright_green(),
+                format_description("Find where pull request #12111 ended up")
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {args}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "freeze") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(&format!(
+                "Turn pull requests into patches in {CONFIG_ROOT}, and use those instead of the pull requests in the config"
+            ));
+
+            let example_1 = format!(
+                "{}
+    {}",
+                "12111".bright_green(),
+                format_description("Replace pull request #12111 with the patch pull-request-12111")
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {args}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "export") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(
+                "Export every pull request and patch merged by the last run as a numbered series of patches, which can be applied with git am",
+            );
+
+            let example_1 = format!(
+                "{}
+    {}",
+                "fork-patches".bright_green(),
+                format_description("Export the fork to the fork-patches directory")
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {args} {flags_label}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {EXPORT_FORMAT_FLAG}
+
+    {GEN_PATCH_DIFF_OPTION_FLAG}
+
+    {GEN_PATCH_DIFF_CONTEXT_FLAG}
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
+",
+            );
+        }
+        Some(cmd_name @ "test-patches") => {
+            let this_command_name = format!("{app_name} {}", cmd_name.bright_yellow());
+
+            let description = format_description(
+                "Fetch upstream into a temporary directory and check that each patch still applies to it, without touching the repository",
+            );
+
+            let example_1 = format!(
+                "{}
+    {}",
+                "--upstream-ref=v25.01".bright_magenta(),
+                format_description("Check the patches against a tag of upstream")
+            );
+
+            println!(
+                "
+{header}
+        
+  Usage:
+
+    {this_command_name} {flags_label}
+    {description}
+
+  Examples:
+
+    {this_command_name} {example_1}
+
+  Flags:
+
+    {TEST_PATCHES_UPSTREAM_REF_FLAG}
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
+    {HELP_FLAG}
 ",
             );
         }
@@ -154,6 +579,16 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
 
     {GEN_PATCH_NAME_FLAG}
 
+    {GEN_PATCH_DIFF_OPTION_FLAG}
+
+    {GEN_PATCH_DIFF_CONTEXT_FLAG}
+
+    {THREADS_FLAG}
+
+    {CONFIG_FLAG}
+
+    {CONFIG_ROOT_FLAG}
+
     {HELP_FLAG}
 ",
             );
@@ -234,6 +669,8 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
 
     {PR_FETCH_REPO_NAME_FLAG}
 
+    {THREADS_FLAG}
+
     {HELP_FLAG}
 ",
             );
@@ -253,6 +690,22 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
 
     {run}
 
+    {run_all}
+
+    {log}
+
+    {list}
+
+    {select}
+
+    {which}
+
+    {freeze}
+
+    {export}
+
+    {test_patches}
+
     {gen_patch} 
 
     {pr_fetch} 
@@ -262,6 +715,10 @@ pub fn help(command: Option<&str>) -> anyhow::Result<()> {
     {HELP_FLAG}
 
     {VERSION_FLAG}
+
+    {REDACT_FLAG}
+
+    {SELFTEST_FLAG}
 "
             );
         }
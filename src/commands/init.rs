@@ -5,17 +5,14 @@ use std::{
 
 use colored::Colorize;
 
-use crate::{
-    confirm_prompt, git_commands::GIT_ROOT, success, types::CommandArgs, CONFIG_FILE, CONFIG_ROOT,
-    INDENT,
-};
+use crate::{confirm_prompt, git_commands::CONFIG_LOCATION, success, types::CommandArgs, INDENT};
 
 pub fn init(_args: &CommandArgs) -> anyhow::Result<()> {
     let example_config = include_bytes!("../../example-config.toml");
 
-    let config_path = GIT_ROOT.join(CONFIG_ROOT);
+    let config_path = &CONFIG_LOCATION.root;
 
-    let config_file_path = config_path.join(CONFIG_FILE);
+    let config_file_path = &CONFIG_LOCATION.file;
 
     if config_file_path.exists()
         && !confirm_prompt!(
@@ -26,9 +23,9 @@ pub fn init(_args: &CommandArgs) -> anyhow::Result<()> {
         anyhow::bail!("Did not overwrite {config_file_path:?}");
     }
 
-    let _ = fs::create_dir(config_path);
+    let _ = fs::create_dir_all(config_path);
 
-    let mut file = File::create(&config_file_path)?;
+    let mut file = File::create(config_file_path)?;
 
     file.write_all(example_config)?;
 
@@ -0,0 +1,127 @@
+use std::{fmt::Write, fs};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::{stream, StreamExt};
+
+use crate::{
+    flags::{Flag, THREADS},
+    git_commands::{request_pull_request, CONFIG_LOCATION},
+    info,
+    types::{CommandArgs, Configuration},
+    utils::{display_link, page},
+    CONFIG_FILE, CONFIG_ROOT, INDENT,
+};
+
+pub static LIST_INCLUDE_BODY_FLAG: Flag<'static> = Flag {
+    short: "-b",
+    long: "--include-body",
+    description: "Also show the description of each pull request",
+};
+
+pub static LIST_BODY_LINES_FLAG: Flag<'static> = Flag {
+    short: "-l=",
+    long: "--body-lines=",
+    description: "How many lines of each description to show with --include-body. Defaults to 5",
+};
+
+/// Lines of the description shown when `--body-lines` is not passed
+static DEFAULT_BODY_LINES: usize = 5;
+
+pub async fn list(args: &CommandArgs) -> anyhow::Result<()> {
+    let include_body = LIST_INCLUDE_BODY_FLAG.is_in_args(args);
+
+    let body_lines = match args
+        .iter()
+        .find_map(|arg| LIST_BODY_LINES_FLAG.extract_from_arg(arg))
+    {
+        Some(body_lines) => body_lines.parse::<usize>().map_err(|_| {
+            anyhow!(
+                "Invalid value for {}: {body_lines}, expected a number",
+                LIST_BODY_LINES_FLAG.long.trim_end_matches('=')
+            )
+        })?,
+        None => DEFAULT_BODY_LINES,
+    };
+
+    let config_raw = fs::read_to_string(&CONFIG_LOCATION.file).map_err(|err| {
+        anyhow!(
+            "Could not read configuration file at {}\n{err}",
+            CONFIG_LOCATION.file.to_string_lossy()
+        )
+    })?;
+
+    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+        anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+    })?;
+
+    if config.pull_requests.is_empty() {
+        info!("There are no pull requests in {CONFIG_ROOT}/{CONFIG_FILE}");
+        return Ok(());
+    }
+
+    let pull_requests: Vec<_> = config
+        .pull_requests
+        .iter()
+        .map(|pull_request| pull_request.number_and_commit().0)
+        .collect();
+
+    let client = reqwest::Client::new();
+
+    let responses: Vec<_> = stream::iter(
+        pull_requests
+            .iter()
+            .map(|pull_request| request_pull_request(&config.repo, pull_request, &client)),
+    )
+    .buffered(*THREADS)
+    .collect()
+    .await;
+
+    let mut output = String::from("\n");
+
+    for (pull_request, response) in pull_requests.iter().zip(responses) {
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                writeln!(
+                    output,
+                    "{INDENT}{}{} {}\n",
+                    "#".bright_blue(),
+                    pull_request.bright_blue(),
+                    err.to_string().trim().bright_red()
+                )?;
+                continue;
+            }
+        };
+
+        writeln!(
+            output,
+            "{INDENT}{}{} {}",
+            "#".bright_blue(),
+            pull_request.bright_blue(),
+            display_link(&response.title, &response.html_url)
+                .blue()
+                .italic()
+        )?;
+
+        if include_body {
+            let body = response.body.as_deref().unwrap_or_default().trim();
+
+            if body.is_empty() {
+                writeln!(output, "{INDENT}{INDENT}{}", "no description".italic())?;
+            }
+
+            for line in body.lines().take(body_lines) {
+                writeln!(output, "{INDENT}{INDENT}{}", line.trim_end())?;
+            }
+
+            if body.lines().count() > body_lines {
+                writeln!(output, "{INDENT}{INDENT}{}", "…".bright_black())?;
+            }
+
+            writeln!(output)?;
+        }
+    }
+
+    page(&output, args)
+}
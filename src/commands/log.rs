@@ -0,0 +1,59 @@
+use std::fmt::Write;
+
+use colored::Colorize;
+
+use crate::{
+    info,
+    run_log::read_run_log,
+    types::CommandArgs,
+    utils::{format_timestamp, page},
+    CONFIG_ROOT, INDENT, LOG_FILE,
+};
+
+pub fn log(args: &CommandArgs) -> anyhow::Result<()> {
+    let entries = read_run_log()?;
+
+    if entries.is_empty() {
+        info!("No runs have been recorded in {CONFIG_ROOT}/{LOG_FILE} yet");
+        return Ok(());
+    }
+
+    // the log grows with every run, so it is shown through a pager
+    let mut output = String::from("\n");
+
+    for entry in entries.iter().rev() {
+        writeln!(
+            output,
+            "{INDENT}{} {} {}",
+            entry.sha.bright_yellow(),
+            format_timestamp(entry.timestamp).bright_black(),
+            format!(
+                "(config {})",
+                entry.config_hash.get(..7).unwrap_or_default()
+            )
+            .bright_black()
+        )?;
+
+        if entry.pull_requests.is_empty() {
+            writeln!(output, "{INDENT}{INDENT}{}", "no pull requests".italic())?;
+        }
+
+        for pull_request in &entry.pull_requests {
+            writeln!(
+                output,
+                "{INDENT}{INDENT}{}{} {}",
+                "#".bright_blue(),
+                pull_request.number.bright_blue(),
+                pull_request
+                    .commit
+                    .get(..7)
+                    .unwrap_or_default()
+                    .bright_black()
+            )?;
+        }
+
+        writeln!(output)?;
+    }
+
+    page(&output, args)
+}
@@ -1,11 +1,31 @@
+pub mod export;
+pub mod freeze;
 pub mod gen_patch;
 pub mod help;
 pub mod init;
+pub mod list;
+pub mod log;
 pub mod pr_fetch;
 pub mod run;
+pub mod run_all;
+pub mod select;
+pub mod selftest;
+pub mod test_patches;
+pub mod watch;
+pub mod which;
 
+pub use export::export;
+pub use freeze::freeze;
 pub use gen_patch::gen_patch;
 pub use help::help;
 pub use init::init;
+pub use list::list;
+pub use log::log;
 pub use pr_fetch::pr_fetch;
 pub use run::run;
+pub use run_all::run_all;
+pub use select::select;
+pub use selftest::selftest;
+pub use test_patches::test_patches;
+pub use watch::watch;
+pub use which::which;
@@ -1,16 +1,18 @@
 use crate::commands::help;
 use crate::fail;
-use crate::flags::{is_valid_flag, Flag};
+use crate::flags::{is_valid_flag, Flag, THREADS};
 use crate::git_commands::{
-    fetch_pull_request, is_valid_branch_name, GIT, GITHUB_REMOTE_PREFIX, GITHUB_REMOTE_SUFFIX,
+    fetch_pull_request, is_valid_branch_name, request_pull_request, GIT, GITHUB_REMOTE_PREFIX,
+    GITHUB_REMOTE_SUFFIX,
 };
 use crate::success;
-use crate::types::CommandArgs;
+use crate::types::{CommandArgs, FetchFrom};
 use crate::utils::display_link;
 use anyhow::anyhow;
 use colored::Colorize;
+use futures::{stream, StreamExt};
 
-use super::help::{HELP_FLAG, VERSION_FLAG};
+use super::help::{HELP_FLAG, REDACT_FLAG, THREADS_FLAG, VERSION_FLAG};
 use super::run::parse_if_maybe_hash;
 
 /// Allow users to prefix their PRs with octothorpe, e.g. #12345 instead of 12345.
@@ -43,12 +45,14 @@ pub static PR_FETCH_REPO_NAME_FLAG: Flag<'static> = Flag {
         "Choose a github repository, using the `origin` remote of the current repository by default",
 };
 
-pub static PR_FETCH_FLAGS: &[&Flag<'static>; 5] = &[
+pub static PR_FETCH_FLAGS: &[&Flag<'static>; 7] = &[
     &PR_FETCH_BRANCH_NAME_FLAG,
     &PR_FETCH_CHECKOUT_FLAG,
     &PR_FETCH_REPO_NAME_FLAG,
+    &THREADS_FLAG,
     &HELP_FLAG,
     &VERSION_FLAG,
+    &REDACT_FLAG,
 ];
 
 pub async fn pr_fetch(args: &CommandArgs) -> anyhow::Result<()> {
@@ -134,20 +138,32 @@ pub async fn pr_fetch(args: &CommandArgs) -> anyhow::Result<()> {
 
     let client = reqwest::Client::new();
 
-    for (i, (pull_request, maybe_custom_branch_name, hash)) in
+    let responses: Vec<_> = stream::iter(
         pull_requests_with_maybe_custom_branch_names
             .iter()
+            .map(|(pull_request, _, _)| request_pull_request(&remote_name, pull_request, &client)),
+    )
+    .buffered(*THREADS)
+    .collect()
+    .await;
+
+    for (i, ((pull_request, maybe_custom_branch_name, hash), response)) in
+        pull_requests_with_maybe_custom_branch_names
+            .iter()
+            .zip(responses)
             .enumerate()
     {
-        match fetch_pull_request(
-            &remote_name,
-            pull_request,
-            &client,
-            maybe_custom_branch_name.as_deref(),
-            hash,
-        )
-        .await
-        {
+        match response.and_then(|response| {
+            fetch_pull_request(
+                response,
+                pull_request,
+                maybe_custom_branch_name.as_deref(),
+                hash,
+                &remote_name,
+                FetchFrom::Branch,
+                None,
+            )
+        }) {
             Ok((response, info)) => {
                 success!(
                     "Fetched pull request {} available at branch {}{}",
@@ -1,21 +1,48 @@
-use std::fs;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
 use colored::Colorize;
+use futures::{stream, StreamExt};
 
 use crate::{
     backup::{backup_files, restore_backup},
     commands::{init, pr_fetch::ignore_octothorpe},
-    confirm_prompt, fail,
-    flags::Flag,
+    confirm_prompt,
+    events::{emit, Event},
+    fail,
+    flags::{Flag, THREADS},
     git_commands::{
-        add_remote_branch, checkout_from_remote, clean_up_remote, fetch_pull_request,
-        merge_pull_request, GIT, GIT_ROOT,
+        add_remote_branch, am_args, apply_patch_text, check_commit_count, check_patch,
+        checkout_from_remote, child_env, clean_up_remote, commit_squash_merge, copy_onto_orphan,
+        current_branch_or_commit, fetch_merge_commit, fetch_pull_request,
+        forget_depth_shallow_commits, is_shallow_repository, leave_out_commits_after,
+        merge_pull_request, read_tree_into_index, repo_url, request_default_branch,
+        request_gist_patch, request_pull_request, request_pull_request_list, set_child_env,
+        unmet_patch_condition, upstream_remote_alias, CONFIG_LOCATION, GIST_PREFIX, GIT, GIT_ROOT,
+    },
+    info,
+    lock::GroupLock,
+    profile,
+    redact::{self, redact},
+    resume::{ConflictedPullRequest, ContinueState},
+    retry::{run_deadline_from_args, RetryPolicy},
+    run_log::{append_run_log, read_run_log, MergedPullRequest, RunLogEntry},
+    step_summary::{Outcome, StepSummary},
+    success, trace,
+    types::{
+        Branch, BranchAndRemote, CommandArgs, Configuration, MergeOptions, OnConflict, Patch,
+        PullRequest, QueuedPullRequest, Remote,
+    },
+    utils::{
+        display_link, format_timestamp, is_maintenance_git_command, is_valid_trailer,
+        render_trailer, with_uuid,
     },
-    info, success, trace,
-    types::{Branch, BranchAndRemote, CommandArgs, Configuration, Remote},
-    utils::{display_link, with_uuid},
-    APP_NAME, CONFIG_FILE, CONFIG_ROOT, INDENT,
+    APP_NAME, CONFIG_FILE, CONFIG_ROOT, INDENT, LOG_FILE,
 };
 
 pub static RUN_YES_FLAG: Flag<'static> = Flag {
@@ -24,6 +51,123 @@ pub static RUN_YES_FLAG: Flag<'static> = Flag {
     description: "Do not prompt when overwriting local-branch specified in the config",
 };
 
+pub static RUN_EVENTS_FLAG: Flag<'static> = Flag {
+    short: "-e=",
+    long: "--events=",
+    description: "Emit progress events to stdout for use by other programs. The only supported format is `json`, which emits newline-delimited JSON",
+};
+
+pub static RUN_NO_BACKUP_FLAG: Flag<'static> = Flag {
+    short: "-B",
+    long: "--no-backup",
+    description: "Do not back up the configuration files before switching branches. Only allowed if they are not tracked by git",
+};
+
+pub static RUN_STRICT_FLAG: Flag<'static> = Flag {
+    short: "-s",
+    long: "--strict",
+    description: "Stop the run on the first pull request which conflicts, instead of skipping it",
+};
+
+pub static RUN_MAX_FETCH_RETRIES_PER_PR_FLAG: Flag<'static> = Flag {
+    short: "-r=",
+    long: "--max-fetch-retries-per-pr=",
+    description:
+        "How many times to retry fetching each pull request before skipping it. Defaults to 0",
+};
+
+pub static RUN_FETCH_DEADLINE_FLAG: Flag<'static> = Flag {
+    short: "-f=",
+    long: "--fetch-deadline=",
+    description: "Stop retrying failed fetches once this many seconds have passed",
+};
+
+pub static RUN_ONLY_PATCHES_FLAG: Flag<'static> = Flag {
+    short: "-p",
+    long: "--only-patches",
+    description: "Do not fetch or merge any pull requests. Reapply the patches on top of the pull requests merged by the previous run",
+};
+
+pub static RUN_DRY_RUN_FLAG: Flag<'static> = Flag {
+    short: "-n",
+    long: "--dry-run",
+    description: "Only check that the patches apply on top of the pull requests merged by the previous run, without changing anything",
+};
+
+pub static RUN_CONCURRENCY_GROUP_FLAG: Flag<'static> = Flag {
+    short: "-g=",
+    long: "--concurrency-group=",
+    description: "Wait for other runs with the same concurrency group in this repository to finish before changing it",
+};
+
+pub static RUN_STEP_SUMMARY_FLAG: Flag<'static> = Flag {
+    short: "-S",
+    long: "--step-summary",
+    description: "When running in GitHub Actions, add a summary of the run to the job's page",
+};
+
+pub static RUN_DEPTH_FLAG: Flag<'static> = Flag {
+    short: "-D=",
+    long: "--depth=",
+    description: "Only fetch this many commits of each pull request's history, unless it has its own `depth` in the config",
+};
+
+pub static RUN_PROFILE_FLAG: Flag<'static> = Flag {
+    short: "-t",
+    long: "--profile",
+    description: "Show how long each phase of the run took at the end, as JSON with --events=json",
+};
+
+pub static RUN_REPORT_FLAG: Flag<'static> = Flag {
+    short: "-O=",
+    long: "--report=",
+    description:
+        "Write a report of the run to this file, with a test case for each pull request and patch",
+};
+
+pub static RUN_REPORT_FORMAT_FLAG: Flag<'static> = Flag {
+    short: "-F=",
+    long: "--report-format=",
+    description:
+        "Format of the --report. The only supported format is `junit`, which is the default",
+};
+
+pub static RUN_VERIFY_CLEAN_AFTER_FLAG: Flag<'static> = Flag {
+    short: "-c",
+    long: "--verify-clean-after",
+    description: "Fail if the working tree has any changes after the run, which would be a bug",
+};
+
+pub static RUN_WATCH_FLAG: Flag<'static> = Flag {
+    short: "-w",
+    long: "--watch",
+    description: "Run again whenever the config or the patches change, until stopped with Ctrl-C",
+};
+
+pub static RUN_DEADLINE_FLAG: Flag<'static> = Flag {
+    short: "-d=",
+    long: "--deadline=",
+    description: "Stop the run once this many seconds have passed, after the pull request currently being merged",
+};
+
+pub static RUN_UNSHALLOW_FLAG: Flag<'static> = Flag {
+    short: "-u",
+    long: "--unshallow",
+    description: "If this repository is a shallow clone, fetch its full history before running",
+};
+
+pub static RUN_CONTINUE_FLAG: Flag<'static> = Flag {
+    short: "-k",
+    long: "--continue",
+    description: "Continue a run which stopped at a conflict because of `on-conflict = \"leave\"`, once the conflict is resolved",
+};
+
+pub static RUN_OVERRIDE_READONLY_FLAG: Flag<'static> = Flag {
+    short: "-o",
+    long: "--override-readonly",
+    description: "Run even if the config is marked as `readonly`",
+};
+
 /// Parses user inputs of the form "(<anything>)+ @ <commit-hash>"
 ///
 /// Returns the user's input but also the commit hash if it exists
@@ -43,16 +187,425 @@ pub fn parse_if_maybe_hash(input: &str, syntax: &str) -> (String, Option<String>
     }
 }
 
+/// Finds the commit on which the previous run applied the patches, and the pull requests merged until then
+fn previous_run_base(
+    local_branch: &str,
+    flag: &Flag,
+) -> anyhow::Result<(String, Vec<MergedPullRequest>)> {
+    let previous_run = read_run_log()?.pop().ok_or_else(|| {
+        anyhow!(
+            "Cannot use {} because there are no previous runs in {CONFIG_ROOT}/{LOG_FILE}",
+            flag.long.bright_magenta()
+        )
+    })?;
+
+    let local_branch_sha = GIT(&["rev-parse", local_branch])?;
+
+    match previous_run.base {
+        Some(base) if previous_run.sha == local_branch_sha => {
+            Ok((base, previous_run.pull_requests))
+        }
+        Some(_) => Err(anyhow!(
+            "Cannot use {} because {} has changed since the previous run",
+            flag.long.bright_magenta(),
+            local_branch.cyan()
+        )),
+        None => Err(anyhow!(
+            "Cannot use {} because the previous run was made by an older version of {APP_NAME}",
+            flag.long.bright_magenta()
+        )),
+    }
+}
+
+/// Checks that every patch applies to `base` of `repo` one after another, without creating any commits
+/// or touching the working tree. `target` describes `base` in the error.
+///
+/// A repository without the history of `base` can't tell whether upstream is at least at a `min-tag`,
+/// so those patches are skipped. The other conditions only need the tree of `base`
+pub async fn check_patches(
+    patches: &[Patch],
+    repo: &Path,
+    base: &str,
+    has_history: bool,
+    target: &str,
+    client: &reqwest::Client,
+) -> anyhow::Result<()> {
+    let index_dir = tempfile::tempdir()?;
+    let index = index_dir.path().join("index");
+
+    read_tree_into_index(repo, base, &index)?;
+
+    let mut failed_patches = 0;
+
+    for patch in patches {
+        let name = patch.name();
+
+        if let Some(condition) = patch.applies_if() {
+            let reason = match condition.min_tag {
+                Some(ref min_tag) if !has_history => Some(format!(
+                    "its `min-tag` {min_tag} can't be checked without the history of upstream"
+                )),
+                _ => unmet_patch_condition(repo, condition, base),
+            };
+
+            if let Some(reason) = reason {
+                info!("Skipping patch {name} because {reason}");
+                continue;
+            }
+        }
+
+        let result = if let Some(gist_id) = name.strip_prefix(GIST_PREFIX) {
+            match request_gist_patch(gist_id, client).await {
+                Ok(patch_text) => {
+                    let patch_path = index_dir.path().join(with_uuid("gist.patch"));
+                    fs::write(&patch_path, patch_text)?;
+                    check_patch(repo, &patch_path, &index)
+                }
+                Err(err) => Err(err),
+            }
+        } else {
+            let patch_path = CONFIG_LOCATION.root.join(format!("{name}.patch"));
+
+            if patch_path.exists() {
+                check_patch(repo, &patch_path, &index)
+            } else {
+                Err(anyhow!(
+                    "Could not find patch {name} at {CONFIG_ROOT}/{name}.patch"
+                ))
+            }
+        };
+
+        match result {
+            Ok(()) => success!("Patch {name} applies"),
+            Err(err) => {
+                failed_patches += 1;
+                fail!("Patch {name} does not apply\n{err}");
+            }
+        }
+    }
+
+    if failed_patches > 0 {
+        return Err(anyhow!(
+            "{failed_patches} patch{} would not apply to {target}",
+            if failed_patches == 1 { "" } else { "es" }
+        ));
+    }
+
+    eprintln!(
+        "\n{INDENT}{}",
+        "  All patches apply!\n".bright_green().bold()
+    );
+
+    Ok(())
+}
+
+/// Checks that every patch still applies to the upstream of the previous run
+async fn dry_run(config: &Configuration, client: &reqwest::Client) -> anyhow::Result<()> {
+    let (base, _) = previous_run_base(&config.local_branch, &RUN_DRY_RUN_FLAG)?;
+
+    check_patches(
+        config.patches.as_deref().unwrap_or_default(),
+        &GIT_ROOT,
+        &base,
+        true,
+        "the upstream of the previous run",
+        client,
+    )
+    .await
+}
+
+/// Commits the resolved conflict of a run which was stopped by `on-conflict = "leave"`,
+/// and puts the config files back so that the run can continue
+fn commit_resolved_conflict(state: &mut ContinueState) -> anyhow::Result<()> {
+    let conflict = &state.conflict;
+
+    let unresolved_files = GIT(&["diff", "--name-only", "--diff-filter=U"])?;
+    if !unresolved_files.is_empty() {
+        return Err(anyhow!(
+            "Pull request #{} still has conflicts in these files:\n{unresolved_files}\n  Once they are resolved, mark them as such with {}",
+            conflict.number,
+            "git add".bright_magenta()
+        ));
+    }
+
+    if let Some(ref backups) = state.backups {
+        fs::create_dir_all(&CONFIG_LOCATION.root)?;
+        for (file_name, contents) in backups {
+            restore_backup(&file_name.into(), contents)
+                .map_err(|err| anyhow!("Could not restore backups:\n{err}"))?;
+        }
+    }
+
+    // the conflict could have been resolved by keeping none of the changes
+    if GIT(&["diff", "--cached", "--quiet"]).is_err() {
+        commit_squash_merge(
+            &conflict.branch,
+            &MergeOptions {
+                trailers: &conflict.trailers,
+                preserve_dates: conflict.preserve_dates,
+                signoff: conflict.signoff,
+                ..Default::default()
+            },
+        )?;
+    }
+
+    clean_up_remote(&conflict.remote, &conflict.branch)?;
+
+    success!(
+        "Merged pull request {}{} with the resolved conflict",
+        "#".bright_blue(),
+        conflict.number.bright_blue()
+    );
+
+    state.merged_pull_requests.push(MergedPullRequest {
+        number: conflict.number.clone(),
+        branch: conflict.branch.clone(),
+        commit: GIT(&["rev-parse", "HEAD"])?,
+    });
+    state
+        .outcomes
+        .push((conflict.number.clone(), Outcome::Merged));
+
+    ContinueState::remove()
+}
+
+/// How many commits of each pull request to fetch, from `--depth`
+fn depth_from_args(args: &CommandArgs) -> anyhow::Result<Option<u32>> {
+    args.iter()
+        .find_map(|arg| RUN_DEPTH_FLAG.extract_from_arg(arg))
+        .map(|depth| {
+            depth
+                .parse::<u32>()
+                .ok()
+                .filter(|depth| *depth > 0)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid value for {}: {depth}, expected a positive number",
+                        RUN_DEPTH_FLAG.long.trim_end_matches('=')
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// Where to write the `--report`, which is checked before the run so that a typo doesn't waste it
+fn report_path(args: &CommandArgs) -> anyhow::Result<Option<String>> {
+    let report = args
+        .iter()
+        .find_map(|arg| RUN_REPORT_FLAG.extract_from_arg(arg));
+    let format = args
+        .iter()
+        .find_map(|arg| RUN_REPORT_FORMAT_FLAG.extract_from_arg(arg));
+
+    match (report, format) {
+        (_, Some(format)) if format != "junit" => Err(anyhow!(
+            "Unsupported report format {format}, the only supported format is junit"
+        )),
+        (None, Some(_)) => Err(anyhow!(
+            "{} needs {} to know where to write the report",
+            RUN_REPORT_FORMAT_FLAG
+                .long
+                .trim_end_matches('=')
+                .bright_magenta(),
+            RUN_REPORT_FLAG.long.trim_end_matches('=').bright_magenta()
+        )),
+        (Some(report), _) if report.is_empty() => Err(anyhow!(
+            "{} needs a path to write the report to",
+            RUN_REPORT_FLAG.long.trim_end_matches('=').bright_magenta()
+        )),
+        (report, _) => Ok(report),
+    }
+}
+
+/// Outcomes of the run, written to the `--step-summary` and the `--report` if they were requested once
+/// it's dropped, which is at the end of the run even if it fails
+struct Reports<'a> {
+    args: &'a CommandArgs,
+    step_summary: StepSummary,
+    retries: BTreeMap<String, usize>,
+}
+
+impl Drop for Reports<'_> {
+    fn drop(&mut self) {
+        if RUN_STEP_SUMMARY_FLAG.is_in_args(self.args) {
+            match self.step_summary.write(&self.retries) {
+                Ok(true) => trace!("Wrote summary to $GITHUB_STEP_SUMMARY"),
+                Ok(false) => info!(
+                    "Not writing a summary despite the {} flag, since $GITHUB_STEP_SUMMARY is not set",
+                    RUN_STEP_SUMMARY_FLAG.long.bright_magenta()
+                ),
+                Err(err) => fail!("Could not write summary to $GITHUB_STEP_SUMMARY\n{err}"),
+            }
+        }
+
+        if let Ok(Some(report)) = report_path(self.args) {
+            match fs::write(&report, self.step_summary.to_junit(&self.retries)) {
+                Ok(()) => trace!("Wrote JUnit report to {report}"),
+                Err(err) => fail!("Could not write the report to {report}\n{err}"),
+            }
+        }
+    }
+}
+
+/// Runs the `format-changed` command on the files which the run changed since `run_start`,
+/// and commits what it changed
+fn format_changed_files(
+    command: &[String],
+    run_start: &str,
+    config_pathspec: &Option<String>,
+) -> anyhow::Result<()> {
+    let mut diff_args = vec!["diff", "--name-only", "--diff-filter=d", run_start, "HEAD"];
+    let exclude_config = config_pathspec
+        .as_ref()
+        .map(|pathspec| format!(":(exclude){pathspec}"));
+
+    if let Some(ref exclude_config) = exclude_config {
+        diff_args.extend(["--", ".", exclude_config]);
+    }
+
+    let changed_files = GIT(&diff_args)?;
+
+    if changed_files.is_empty() {
+        return Ok(());
+    }
+
+    let [program, command_args @ ..] = command else {
+        return Ok(());
+    };
+
+    trace!(
+        "Running {} on the {} files changed by the run",
+        command.join(" "),
+        changed_files.lines().count()
+    );
+
+    let output = std::process::Command::new(program)
+        .args(command_args)
+        .args(changed_files.lines())
+        .envs(child_env())
+        .current_dir(&*GIT_ROOT)
+        .output()
+        .map_err(|err| anyhow!("Could not run the format-changed command `{program}`\n{err}"))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "The format-changed command `{}` failed\nStdout: {}\nStderr: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let mut formatted_args = vec!["diff", "--name-only", "--"];
+    formatted_args.extend(changed_files.lines());
+    let formatted_files = GIT(&formatted_args)?;
+
+    if formatted_files.is_empty() {
+        info!("The format-changed command did not change any files");
+        return Ok(());
+    }
+
+    let mut add_args = vec!["add", "--"];
+    add_args.extend(formatted_files.lines());
+    GIT(&add_args)?;
+    GIT(&[
+        "commit",
+        "--message",
+        &format!("{APP_NAME}: Format changed files"),
+    ])?;
+
+    success!(
+        "Formatted {} files:\n{}",
+        formatted_files.lines().count().to_string().bright_blue(),
+        formatted_files
+            .lines()
+            .map(|file| format!("{INDENT}{}", file.bright_cyan()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    Ok(())
+}
+
+/// Merges the pull requests and applies the patches of the config on top of upstream, into `local-branch`
+///
+/// # Examples
+///
+/// A run which fails after checking out the branch it works on goes back to where it started,
+/// even if that was a detached HEAD
+///
+/// ```rust
+/// # use patchy::test_repo::{commit, git, temp_repo};
+/// # let upstream = temp_repo();
+/// # commit("base");
+/// # for pull_request in ["1", "2"] {
+/// #     git(&["switch", "--quiet", "--create", pull_request, "main"]);
+/// #     std::fs::write("base", pull_request).unwrap();
+/// #     git(&["commit", "--quiet", "--all", &format!("--message={pull_request}")]);
+/// #     git(&["update-ref", &format!("refs/pull/{pull_request}/head"), "HEAD"]);
+/// # }
+/// # git(&["switch", "--quiet", "main"]);
+/// # let _fork = temp_repo();
+/// # std::fs::create_dir(".patchy").unwrap();
+/// # let config = format!(
+/// #     "repo = \"{}\"\nremote-branch = \"main\"\nlocal-branch = \"patchy\"\npull-requests = [\"1\", \"2\"]\nstrict = true\n",
+/// #     upstream.path().display()
+/// # );
+/// # std::fs::write(".patchy/config.toml", config).unwrap();
+/// # git(&["add", "."]);
+/// # git(&["commit", "--quiet", "--message=config"]);
+/// # commit("second");
+/// use patchy::{commands::run, types::CommandArgs};
+///
+/// git(&["checkout", "--quiet", "--detach", "HEAD~1"]);
+/// let started_at = git(&["rev-parse", "HEAD"]);
+///
+/// // both pull requests change the same line, and `strict` stops the run at the conflict
+/// let args: CommandArgs = ["--yes".to_owned()].into_iter().collect();
+/// let run = tokio::runtime::Runtime::new().unwrap().block_on(run(&args));
+/// assert!(run.is_err());
+///
+/// assert_eq!(git(&["rev-parse", "--abbrev-ref", "HEAD"]), "HEAD");
+/// assert_eq!(git(&["rev-parse", "HEAD"]), started_at);
+/// ```
 pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
-    println!();
+    eprintln!();
+
+    // the report is shown after the run is timed, since they are dropped in reverse order
+    let _report = profile::report();
+    let _timer = profile::start("run");
 
-    let config_path = GIT_ROOT.join(CONFIG_ROOT);
+    report_path(args)?;
+    let depth = depth_from_args(args)?;
+
+    // the config files may not be in the working tree of a stopped run, so this happens before reading them
+    let continued = if RUN_CONTINUE_FLAG.is_in_args(args) {
+        let mut state = ContinueState::load()?.ok_or_else(|| {
+            anyhow!("There is no run which stopped at a conflict, so there is nothing to continue")
+        })?;
+        commit_resolved_conflict(&mut state)?;
+        Some(state)
+    } else if let Some(state) = ContinueState::load()? {
+        return Err(anyhow!(
+            "The previous run stopped at a conflict. Resolve it and run {}, or give up on it with {} and by removing {}",
+            format!("{APP_NAME} run {}", RUN_CONTINUE_FLAG.long).bright_magenta(),
+            format!("git reset --hard && git checkout {}", state.previous_branch).bright_magenta(),
+            ContinueState::path()?.display()
+        ));
+    } else {
+        None
+    };
+
+    let config_path = &CONFIG_LOCATION.root;
     let has_yes_flag = RUN_YES_FLAG.is_in_args(args);
 
-    let config_file_path = config_path.join(CONFIG_FILE);
+    let config_file_path = &CONFIG_LOCATION.file;
 
-    let Ok(config_raw) = fs::read_to_string(config_file_path.clone()) else {
-        fail!("Could not find configuration file at {CONFIG_ROOT}/{CONFIG_FILE}");
+    let Ok(config_raw) = fs::read_to_string(config_file_path) else {
+        fail!(
+            "Could not find configuration file at {}",
+            config_file_path.to_string_lossy()
+        );
 
         // We don't want to have *any* sort of prompt when using the -y flag since that would be problematic in scripts
         if !has_yes_flag
@@ -62,10 +615,7 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
                 "init".bright_yellow(),
             )
         {
-            if let Err(err) = init(args) {
-                fail!("{err}");
-                std::process::exit(1);
-            };
+            init(args)?;
         } else if has_yes_flag {
             eprintln!(
                 "You can create it with {} {}",
@@ -76,16 +626,77 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
 
         // We don't want to read the default configuration file as config_raw. Since it's empty there's no reason why the user would want to run it.
 
-        std::process::exit(0);
+        return Ok(());
     };
 
     trace!("Using configuration file {config_file_path:?}");
 
-    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+    let mut config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
         anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
     })?;
 
-    let (remote_branch, commit_hash) = parse_if_maybe_hash(&config.remote_branch, " @ ");
+    // the repository can also be a path or a name which doesn't look like `owner/repo`
+    redact::register("repo", &config.repo);
+
+    if config.readonly && !RUN_OVERRIDE_READONLY_FLAG.is_in_args(args) {
+        return Err(anyhow!(
+            "The config at {CONFIG_ROOT}/{CONFIG_FILE} is marked as `readonly`, so {} {} will not run.\n  If you're sure, pass the {} flag",
+            "patchy".bright_blue(),
+            "run".bright_yellow(),
+            RUN_OVERRIDE_READONLY_FLAG.long.bright_magenta()
+        ));
+    }
+
+    if let Some(trailer) = config
+        .commit_trailers
+        .iter()
+        .find(|trailer| !is_valid_trailer(trailer))
+    {
+        return Err(anyhow!(
+            "Invalid commit trailer {trailer:?}, trailers must be of the form `Token: value`, e.g. \"Upstream-PR: {{repo}}#{{pull_request}}\""
+        ));
+    }
+
+    if let Some(command) = config
+        .pre_fetch_git
+        .iter()
+        .find(|command| !is_maintenance_git_command(command))
+    {
+        return Err(anyhow!(
+            "Refusing to run `git {}` from `pre-fetch-git`, only read and maintenance commands such as `gc`, `repack` or `maintenance run` are allowed",
+            command.join(" ")
+        ));
+    }
+
+    if let Some(pull_request) = config
+        .pull_requests
+        .iter()
+        .find(|pull_request| pull_request.use_merge_commit() && pull_request.up_to().is_some())
+    {
+        return Err(anyhow!(
+            "Pull request #{} can't have both `up-to` and `use-merge-commit`, since the commit which merged it already includes all of its commits",
+            pull_request.number_and_commit().0
+        ));
+    }
+
+    set_child_env(&config.env);
+
+    if is_shallow_repository()? {
+        if RUN_UNSHALLOW_FLAG.is_in_args(args) {
+            info!("Fetching the full history, since this repository is a shallow clone");
+            GIT(&["fetch", "--unshallow"])?;
+            forget_depth_shallow_commits();
+        } else {
+            fail!(
+                "This repository is a shallow clone, so pull requests may fail to merge and `min-tag` conditions may be wrong.\n  Pass the {} flag to fetch the full history first",
+                RUN_UNSHALLOW_FLAG.long.bright_magenta()
+            );
+        }
+    }
+
+    if RUN_DRY_RUN_FLAG.is_in_args(args) {
+        return dry_run(&config, &reqwest::Client::new()).await;
+    }
 
     if config.repo.is_empty() {
         return Err(anyhow::anyhow!(
@@ -97,38 +708,280 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
         ));
     }
 
-    let config_files = fs::read_dir(&config_path).map_err(|err| {
-        anyhow!(
-            "Could not read files in directory {:?}\n{err}",
-            &config_path
-        )
-    })?;
+    let only_patches = RUN_ONLY_PATCHES_FLAG.is_in_args(args);
 
-    let backed_up_files = backup_files(config_files).map_err(|err| {
-        anyhow!("Could not create backups for configuration files, aborting.\n{err}")
-    })?;
+    let client = reqwest::Client::new();
+
+    let remote_branch = match config.remote_branch {
+        Some(ref remote_branch) => remote_branch.clone(),
+        None => request_default_branch(&config.repo, &client).await?,
+    };
+    let (remote_branch, commit_hash) = parse_if_maybe_hash(&remote_branch, " @ ");
+
+    // a continued run already knows which pull requests are left
+    if let (Some(discover), false) = (
+        &config.discover_pull_requests,
+        only_patches || continued.is_some(),
+    ) {
+        let listed_pull_requests: Vec<_> = config
+            .pull_requests
+            .iter()
+            .map(|pull_request| pull_request.number_and_commit().0)
+            .collect();
+        let excluded_pull_requests: Vec<_> = discover
+            .exclude
+            .iter()
+            .map(|pull_request| ignore_octothorpe(pull_request))
+            .collect();
+
+        // pull requests in `pull-requests` are merged first, and keep their commit hashes
+        let discovered_pull_requests: Vec<_> =
+            request_pull_request_list(&config.repo, &remote_branch, discover.state, &client)
+                .await?
+                .into_iter()
+                .filter(|listed| {
+                    !listed_pull_requests.contains(&listed.number)
+                        && !excluded_pull_requests.contains(&listed.number)
+                })
+                .collect();
+
+        info!(
+            "Found {} pull request{} targeting {} in state {}, not counting the ones in `pull-requests` or `exclude`",
+            discovered_pull_requests.len().to_string().bright_blue(),
+            if discovered_pull_requests.len() == 1 { "" } else { "s" },
+            remote_branch.cyan(),
+            discover.state.to_string().bright_yellow()
+        );
+
+        for discovered in &discovered_pull_requests {
+            eprintln!(
+                "{INDENT}{INDENT}{}{} {}",
+                "#".bright_blue(),
+                discovered.number.bright_blue(),
+                format!("({})", discovered.state).bright_black()
+            );
+        }
+
+        if !discovered_pull_requests.is_empty()
+            && !has_yes_flag
+            && !confirm_prompt!("Merge all {} of them?", discovered_pull_requests.len())
+        {
+            return Err(anyhow!(
+                "Not merging the discovered pull requests. To only merge the ones in `pull-requests`, remove `discover-pull-requests` from the config"
+            ));
+        }
+
+        config.pull_requests.extend(
+            discovered_pull_requests
+                .into_iter()
+                .map(|discovered| PullRequest::Number(discovered.number)),
+        );
+    }
+
+    // The pull requests are already merged into `local-branch`, we just need to find where the patches begin
+    let previous_run = if only_patches {
+        Some(previous_run_base(
+            &config.local_branch,
+            &RUN_ONLY_PATCHES_FLAG,
+        )?)
+    } else {
+        None
+    };
+
+    let config_pathspec = CONFIG_LOCATION.pathspec();
+
+    let backed_up_files = if RUN_NO_BACKUP_FLAG.is_in_args(args) {
+        // Untracked files are left alone when switching branches, so they don't need to be backed up.
+        // Neither do files outside of the repository
+        let tracked_config_files = match config_pathspec {
+            Some(ref pathspec) => GIT(&["ls-files", "--", pathspec])?,
+            None => String::new(),
+        };
+
+        if !tracked_config_files.is_empty() {
+            return Err(anyhow!(
+                "Cannot use {} because the following files in {CONFIG_ROOT} are tracked by git, and would be lost when switching branches:\n{tracked_config_files}",
+                RUN_NO_BACKUP_FLAG.long.bright_magenta()
+            ));
+        }
+
+        None
+    } else {
+        let config_files = fs::read_dir(config_path).map_err(|err| {
+            anyhow!(
+                "Could not read files in directory {:?}\n{err}",
+                &config_path
+            )
+        })?;
+
+        let _timer = profile::start("backup");
+        Some(backup_files(config_files).map_err(|err| {
+            anyhow!("Could not create backups for configuration files, aborting.\n{err}")
+        })?)
+    };
 
     let info = BranchAndRemote {
         branch: Branch {
             upstream_branch_name: remote_branch.clone(),
-            local_branch_name: with_uuid(&remote_branch),
+            local_branch_name: match continued {
+                Some(ref state) => state.upstream_branch.clone(),
+                None => with_uuid(&remote_branch),
+            },
         },
         remote: Remote {
-            repository_url: format!("https://github.com/{}.git", config.repo),
-            local_remote_alias: with_uuid(&config.repo),
+            repository_url: repo_url(&config.repo),
+            local_remote_alias: match continued {
+                Some(ref state) => state.upstream_remote.clone(),
+                None => upstream_remote_alias(&config.repo),
+            },
         },
     };
 
-    add_remote_branch(&info, &commit_hash)?;
+    // Nothing is fetched when only applying patches, so there is no remote to remove
+    let clean_up = || -> anyhow::Result<()> {
+        if only_patches {
+            return Ok(());
+        }
+
+        clean_up_remote(
+            &info.remote.local_remote_alias,
+            &info.branch.local_branch_name,
+        )
+    };
 
-    let previous_branch = checkout_from_remote(
-        &info.branch.local_branch_name,
-        &info.remote.local_remote_alias,
-    )?;
+    // Only one run of a concurrency group can change the repository at a time
+    let _group_lock = GroupLock::acquire_from_args(args).await?;
 
-    let client = reqwest::Client::new();
+    if previous_run.is_none() && continued.is_none() && !config.pre_fetch_git.is_empty() {
+        let _timer = profile::start("pre-fetch-git");
+        for command in &config.pre_fetch_git {
+            let command = command.iter().map(String::as_str).collect::<Vec<_>>();
+            trace!("Running git {}", command.join(" "));
+            GIT(&command)
+                .map_err(|err| anyhow!("Could not run `git {}`\n{err}", command.join(" ")))?;
+        }
+    }
 
-    if config.pull_requests.is_empty() {
+    let starting_point = match (&previous_run, &continued) {
+        (Some((base, _)), _) => base.clone(),
+        (None, Some(_)) => GIT(&["rev-parse", "HEAD"])?,
+        (None, None) => {
+            let _timer = profile::start("fetch upstream");
+            add_remote_branch(&info, &commit_hash, None)?;
+            info.branch.local_branch_name.clone()
+        }
+    };
+
+    if let (None, Some(pathspec)) = (&backed_up_files, &config_pathspec) {
+        let tracked_upstream_files = GIT(&[
+            "ls-tree",
+            "-r",
+            "--name-only",
+            &starting_point,
+            "--",
+            pathspec,
+        ])?;
+
+        if !tracked_upstream_files.is_empty() {
+            clean_up()?;
+
+            return Err(anyhow!(
+                "Cannot use {} because {} tracks the following files in {CONFIG_ROOT}, which would overwrite yours:\n{tracked_upstream_files}",
+                RUN_NO_BACKUP_FLAG.long.bright_magenta(),
+                remote_branch
+            ));
+        }
+    }
+
+    // Uncommitted changes to the config would prevent switching branches. They are backed up already,
+    // but stashing them as well means that they can be recovered if the run fails
+    let has_stashed_config = match (&backed_up_files, &config_pathspec) {
+        _ if continued.is_some() => continued
+            .as_ref()
+            .is_some_and(|state| state.has_stashed_config),
+        (Some(_), Some(pathspec))
+            if !GIT(&[
+                "status",
+                "--porcelain",
+                "--untracked-files=no",
+                "--",
+                pathspec,
+            ])?
+            .is_empty() =>
+        {
+            GIT(&[
+                "stash",
+                "push",
+                "--quiet",
+                "--message",
+                &format!("{APP_NAME}: uncommitted changes to {CONFIG_ROOT}"),
+                "--",
+                pathspec,
+            ])?;
+            info!(
+                "Stashed your uncommitted changes to {CONFIG_ROOT}. If the run fails, you can get them back with {}",
+                "git stash pop".bright_magenta()
+            );
+            true
+        }
+        _ => false,
+    };
+
+    let previous_branch = if let Some(ref state) = continued {
+        Ok(state.previous_branch.clone())
+    } else if only_patches {
+        current_branch_or_commit().and_then(|current_branch| {
+            GIT(&["switch", "--detach", &starting_point])?;
+            Ok(current_branch)
+        })
+    } else {
+        let _timer = profile::start("checkout");
+        checkout_from_remote(
+            &info.branch.local_branch_name,
+            &info.remote.local_remote_alias,
+        )
+    }
+    .inspect_err(|_| {
+        if has_stashed_config {
+            let _ = GIT(&["stash", "pop", "--quiet"]);
+        }
+    })?;
+
+    // what the run started from, so that the files it changes can be formatted
+    let run_start = match continued {
+        Some(ref state) => state.run_start.clone(),
+        None => GIT(&["rev-parse", "HEAD"])?,
+    };
+
+    let is_strict = config.strict || RUN_STRICT_FLAG.is_in_args(args);
+
+    let retry_policy = RetryPolicy::from_args(args)?;
+    let run_deadline = run_deadline_from_args(args)?;
+    let mut merged_pull_requests = vec![];
+    let mut failed_pull_requests = vec![];
+    let mut reports = Reports {
+        args,
+        step_summary: StepSummary::default(),
+        retries: BTreeMap::new(),
+    };
+
+    if let Some(ref state) = continued {
+        merged_pull_requests.clone_from(&state.merged_pull_requests);
+        failed_pull_requests.clone_from(&state.failed_pull_requests);
+        reports
+            .step_summary
+            .pull_requests
+            .clone_from(&state.outcomes);
+    }
+
+    if let Some((_, ref pull_requests)) = previous_run {
+        info!(
+            "Not merging pull requests because of the {} flag, keeping the {} pull requests merged by the previous run",
+            RUN_ONLY_PATCHES_FLAG.long.bright_magenta(),
+            pull_requests.len()
+        );
+        merged_pull_requests.clone_from(pull_requests);
+    } else if continued.is_none() && config.pull_requests.is_empty() {
         info!(
             "You haven't specified any pull requests to fetch in your config, {}",
             display_link(
@@ -137,24 +990,213 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
             )
         )
     } else {
-        // TODO: make this concurrent, see https://users.rust-lang.org/t/processing-subprocesses-concurrently/79638/3
-        // Git cannot handle multiple threads executing commands in the same repository, so we can't use threads, but we can run processes in the background
-        for pull_request in config.pull_requests.iter() {
-            let pull_request = ignore_octothorpe(pull_request);
-            let (pull_request, commit_hash) = parse_if_maybe_hash(&pull_request, " @ ");
+        let pull_requests: Vec<_> = match continued {
+            Some(ref state) => state.remaining_pull_requests.clone(),
+            None => config
+                .pull_requests
+                .iter()
+                .map(|pull_request| {
+                    let (number, commit_hash) = pull_request.number_and_commit();
+                    QueuedPullRequest {
+                        number,
+                        commit_hash,
+                        up_to: pull_request.up_to().map(String::from),
+                        depth: pull_request.depth().or(depth),
+                        use_merge_commit: pull_request.use_merge_commit(),
+                    }
+                })
+                .collect(),
+        };
+        let queued_pull_requests = pull_requests.clone();
+
+        // Requests to the GitHub API are made concurrently, but git cannot handle multiple
+        // commands executing in the same repository, so the fetching and merging is sequential
+        let requests_timer = profile::start("request pull requests");
+        let responses: Vec<_> = stream::iter(pull_requests.iter().map(|queued| {
+            let pull_request = &queued.number;
+            emit(Event::FetchStart { pull_request });
+            retry_policy.retry(0, || {
+                request_pull_request(&config.repo, pull_request, &client)
+            })
+        }))
+        .buffered(*THREADS)
+        .collect()
+        .await;
+        drop(requests_timer);
+
+        let all_pull_requests: Vec<_> = pull_requests
+            .iter()
+            .map(|queued| queued.number.clone())
+            .collect();
+
+        for (
+            index,
+            (
+                QueuedPullRequest {
+                    number: pull_request,
+                    commit_hash,
+                    up_to,
+                    depth,
+                    use_merge_commit,
+                },
+                (response, request_retries),
+            ),
+        ) in pull_requests.into_iter().zip(responses).enumerate()
+        {
+            if run_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                GIT(&["checkout", &previous_branch])?;
+
+                if has_stashed_config {
+                    GIT(&["stash", "pop", "--quiet"])?;
+                }
+
+                clean_up()?;
+
+                let list = |pull_requests: Vec<&String>| {
+                    if pull_requests.is_empty() {
+                        return "none".to_owned();
+                    }
+
+                    pull_requests
+                        .iter()
+                        .map(|pull_request| format!("#{pull_request}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+
+                return Err(anyhow!(
+                    "Stopped the run because the deadline of the {} flag has passed, without changing {}.\n  Merged: {}\n  Not attempted: {}",
+                    RUN_DEADLINE_FLAG.long.trim_end_matches('=').bright_magenta(),
+                    config.local_branch.cyan(),
+                    list(
+                        merged_pull_requests
+                            .iter()
+                            .map(|merged| &merged.number)
+                            .collect()
+                    ),
+                    list(all_pull_requests[index..].iter().collect())
+                ));
+            }
+
+            let fetch_timer = profile::start(&format!("fetch #{pull_request}"));
+            let (fetched, pull_request_retries) = match response {
+                Ok(response) => {
+                    retry_policy
+                        .retry(request_retries, || async {
+                            fetch_pull_request(
+                                response.clone(),
+                                &pull_request,
+                                None,
+                                &commit_hash,
+                                &config.repo,
+                                config.fetch_from,
+                                depth,
+                            )
+                        })
+                        .await
+                }
+                Err(err) => (Err(err), request_retries),
+            };
+            drop(fetch_timer);
+
+            if pull_request_retries > 0 {
+                reports
+                    .retries
+                    .insert(pull_request.clone(), pull_request_retries);
+            }
+
+            let fetched = fetched.and_then(|(response, pull_request_info)| {
+                let branch = &pull_request_info.branch.local_branch_name;
+
+                // GitHub also has a `merge_commit_sha` for open pull requests, which is a test merge
+                let merge_commit = response
+                    .merged_at
+                    .as_ref()
+                    .and(response.merge_commit_sha.clone())
+                    .filter(|_| use_merge_commit);
+
+                if use_merge_commit && merge_commit.is_none() {
+                    trace!("Pull request #{pull_request} is not merged, so its branch is used instead of `use-merge-commit`");
+                }
+
+                let checked = match merge_commit {
+                    Some(ref merge_commit) => fetch_merge_commit(
+                        branch,
+                        &pull_request,
+                        merge_commit,
+                        response.commits,
+                        &config.repo,
+                    )
+                    .map(Some),
+                    None => match up_to {
+                        Some(ref up_to) => leave_out_commits_after(branch, &pull_request, up_to),
+                        None => Ok(()),
+                    }
+                    .and_then(|()| {
+                        check_commit_count(branch, &pull_request, config.max_pr_commits)
+                    })
+                    .map(|()| None),
+                };
+
+                match checked {
+                    Ok(cherry_pick) => Ok((response, pull_request_info, cherry_pick)),
+                    Err(err) => {
+                        clean_up_remote(&pull_request_info.remote.local_remote_alias, branch)?;
+                        Err(err)
+                    }
+                }
+            });
+
             // TODO: refactor this to not use such deep nesting
-            match fetch_pull_request(&config.repo, &pull_request, &client, None, &commit_hash).await
-            {
-                Ok((response, info)) => {
-                    match merge_pull_request(
-                        info,
+            match fetched {
+                Ok((response, pull_request_info, cherry_pick)) => {
+                    emit(Event::FetchDone {
+                        pull_request: &pull_request,
+                    });
+                    emit(Event::MergeStart {
+                        pull_request: &pull_request,
+                    });
+                    let branch = pull_request_info.branch.local_branch_name.clone();
+                    let remote = pull_request_info.remote.local_remote_alias.clone();
+                    let trailers: Vec<_> = config
+                        .commit_trailers
+                        .iter()
+                        .map(|trailer| {
+                            render_trailer(trailer, &config.repo, &pull_request, &response)
+                        })
+                        .collect();
+                    let merge_timer = profile::start(&format!("merge #{pull_request}"));
+                    let merged = merge_pull_request(
+                        pull_request_info,
                         &pull_request,
                         &response.title,
                         &response.html_url,
+                        &MergeOptions {
+                            trailers: &trailers,
+                            preserve_dates: config.preserve_dates,
+                            leave_conflicts: config.on_conflict == OnConflict::Leave,
+                            signoff: config.merge_signoff,
+                            merged_before: &merged_pull_requests,
+                            cherry_pick,
+                        },
                     )
-                    .await
-                    {
+                    .await;
+                    drop(merge_timer);
+
+                    match merged {
                         Ok(()) => {
+                            emit(Event::MergeDone {
+                                pull_request: &pull_request,
+                            });
+                            merged_pull_requests.push(MergedPullRequest {
+                                number: pull_request.clone(),
+                                branch,
+                                commit: GIT(&["rev-parse", "HEAD"])?,
+                            });
+                            reports
+                                .step_summary
+                                .pull_requests
+                                .push((pull_request.clone(), Outcome::Merged));
                             success!(
                                 "Merged pull request {}",
                                 display_link(
@@ -170,12 +1212,96 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
                             )
                         }
                         Err(err) => {
+                            emit(Event::Conflict {
+                                pull_request: &pull_request,
+                                error: err.to_string(),
+                            });
+                            failed_pull_requests.push(pull_request.clone());
+                            reports
+                                .step_summary
+                                .pull_requests
+                                .push((pull_request.clone(), Outcome::Conflict(err.to_string())));
+
+                            let has_conflicts = !GIT(&["ls-files", "--unmerged"])?.is_empty();
+
+                            if config.on_conflict == OnConflict::Leave && has_conflicts {
+                                let conflicting_files =
+                                    GIT(&["diff", "--name-only", "--diff-filter=U"])?;
+
+                                // the conflicting pull request is merged once it is continued
+                                let mut outcomes = reports.step_summary.pull_requests.clone();
+                                outcomes.pop();
+                                failed_pull_requests.pop();
+
+                                ContinueState {
+                                    previous_branch,
+                                    upstream_remote: info.remote.local_remote_alias.clone(),
+                                    upstream_branch: info.branch.local_branch_name.clone(),
+                                    run_start,
+                                    has_stashed_config,
+                                    conflict: ConflictedPullRequest {
+                                        number: pull_request.clone(),
+                                        branch,
+                                        remote,
+                                        trailers,
+                                        preserve_dates: config.preserve_dates,
+                                        signoff: config.merge_signoff,
+                                    },
+                                    merged_pull_requests,
+                                    failed_pull_requests,
+                                    outcomes,
+                                    remaining_pull_requests: queued_pull_requests[index + 1..]
+                                        .to_vec(),
+                                    backups: backed_up_files.map(|backups| {
+                                        backups
+                                            .into_iter()
+                                            .map(|(file_name, _file, contents)| {
+                                                (file_name.to_string_lossy().into_owned(), contents)
+                                            })
+                                            .collect()
+                                    }),
+                                }
+                                .save()?;
+
+                                return Err(anyhow!(
+                                    "Stopped the run because of a conflict in pull request #{pull_request}, and left it for you to resolve since `on-conflict` is \"leave\". These files have conflicts:\n{conflicting_files}\n  Once they are resolved, mark them as such with {} and run {}",
+                                    "git add".bright_magenta(),
+                                    format!("{APP_NAME} run {}", RUN_CONTINUE_FLAG.long).bright_magenta()
+                                ));
+                            }
+
+                            if is_strict {
+                                GIT(&["checkout", &previous_branch])?;
+
+                                if has_stashed_config {
+                                    GIT(&["stash", "pop", "--quiet"])?;
+                                }
+
+                                clean_up_remote(
+                                    &info.remote.local_remote_alias,
+                                    &info.branch.local_branch_name,
+                                )?;
+
+                                return Err(anyhow!(
+                                    "Stopped the run because of a conflict in pull request #{pull_request}, since strict mode is enabled.\n{err}"
+                                ));
+                            }
+
                             fail!("{err}");
                             continue;
                         }
                     };
                 }
                 Err(err) => {
+                    emit(Event::FetchFailed {
+                        pull_request: &pull_request,
+                        error: err.to_string(),
+                    });
+                    failed_pull_requests.push(pull_request.clone());
+                    reports
+                        .step_summary
+                        .pull_requests
+                        .push((pull_request.clone(), Outcome::FetchFailed(err.to_string())));
                     fail!("Could not fetch branch from remote\n{err}");
                     continue;
                 }
@@ -183,73 +1309,182 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
         }
     }
 
-    if let Err(err) = fs::create_dir_all(GIT_ROOT.join(CONFIG_ROOT)) {
+    let base = GIT(&["rev-parse", "HEAD"])?;
+
+    if let Err(err) = fs::create_dir_all(config_path) {
         GIT(&["checkout", &previous_branch])?;
 
-        clean_up_remote(
-            &info.remote.local_remote_alias,
-            &info.branch.local_branch_name,
-        )?;
+        clean_up()?;
 
         return Err(anyhow!("Could not create directory {CONFIG_ROOT}\n{err}"));
     };
 
-    for (file_name, _file, contents) in backed_up_files.iter() {
+    let restore_timer = profile::start("restore");
+    for (file_name, _file, contents) in backed_up_files.iter().flatten() {
         restore_backup(file_name, contents)
             .map_err(|err| anyhow!("Could not restore backups:\n{err}"))?;
+    }
+    drop(restore_timer);
 
-        // apply patches if they exist
-        if let Some(ref patches) = config.patches {
-            let file_name = file_name
-                .to_str()
-                .and_then(|file_name| file_name.get(0..file_name.len() - 6))
-                .unwrap_or_default();
-
-            if patches.contains(file_name) {
-                if let Err(err) = GIT(&[
-                    "am",
-                    "--keep-cr",
-                    "--signoff",
-                    &format!(
-                        "{}/{file_name}.patch",
-                        GIT_ROOT.join(CONFIG_ROOT).to_str().unwrap_or_default()
-                    ),
-                ]) {
-                    GIT(&["am", "--abort"])?;
-                    return Err(anyhow!(
-                        "Could not apply patch {file_name}, skipping\n{err}"
-                    ));
-                };
+    if has_stashed_config {
+        GIT(&["stash", "drop", "--quiet"])?;
+    }
+
+    let patches_timer = profile::start("apply patches");
+    for patch in config.patches.iter().flatten() {
+        let name = patch.name();
+        let _timer = profile::start(&format!("apply {name}"));
+
+        // the same base as `--dry-run`, so that the patches before this one don't change whether it applies
+        if let Some(reason) = patch
+            .applies_if()
+            .and_then(|condition| unmet_patch_condition(&GIT_ROOT, condition, &base))
+        {
+            info!("Skipping patch {name} because {reason}");
+            reports
+                .step_summary
+                .patches
+                .push((name.to_owned(), Outcome::Skipped(reason)));
+            continue;
+        }
+
+        if let Some(gist_id) = name.strip_prefix(GIST_PREFIX) {
+            let applied = match request_gist_patch(gist_id, &client).await {
+                Ok(patch_text) => {
+                    apply_patch_text(&patch_text, name, config.preserve_dates, config.am_signoff)
+                }
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = applied {
+                reports
+                    .step_summary
+                    .patches
+                    .push((name.to_owned(), Outcome::Failed(err.to_string())));
+                return Err(anyhow!("Could not apply patch {name}, skipping\n{err}"));
+            }
+        } else {
+            let patch_path = config_path.join(format!("{name}.patch"));
 
-                let last_commit_message = GIT(&["log", "-1", "--format=%B"])?;
-                success!(
-                    "Applied patch {file_name} {}",
-                    last_commit_message
-                        .lines()
-                        .next()
-                        .unwrap_or_default()
-                        .bright_blue()
-                        .italic()
-                );
+            if !patch_path.exists() {
+                fail!("Could not find patch {name} at {CONFIG_ROOT}/{name}.patch, skipping");
+                reports.step_summary.patches.push((
+                    name.to_owned(),
+                    Outcome::Skipped(format!("{CONFIG_ROOT}/{name}.patch does not exist")),
+                ));
+                continue;
             }
+
+            let mut am_args = am_args(config.preserve_dates, config.am_signoff);
+            am_args.push(patch_path.to_str().unwrap_or_default());
+
+            if let Err(err) = GIT(&am_args) {
+                reports
+                    .step_summary
+                    .patches
+                    .push((name.to_owned(), Outcome::Failed(err.to_string())));
+                GIT(&["am", "--abort"])?;
+                return Err(anyhow!("Could not apply patch {name}, skipping\n{err}"));
+            };
         }
+
+        reports
+            .step_summary
+            .patches
+            .push((name.to_owned(), Outcome::Applied));
+
+        let last_commit_message = GIT(&["log", "-1", "--format=%B"])?;
+        success!(
+            "Applied patch {name} {}",
+            last_commit_message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .bright_blue()
+                .italic()
+        );
     }
 
-    GIT(&["add", CONFIG_ROOT])?;
-    GIT(&[
-        "commit",
-        "--message",
-        &format!("{APP_NAME}: Restore configuration files"),
-    ])?;
+    drop(patches_timer);
+
+    if !config.format_changed.is_empty() {
+        let _timer = profile::start("format-changed");
+        format_changed_files(&config.format_changed, &run_start, &config_pathspec)?;
+    }
+
+    // A config outside of the repository is unaffected by switching branches, so there is nothing to restore.
+    // Neither is an untracked one with `--no-backup`, which stays untracked so that it can be used again
+    if let (Some(ref pathspec), Some(_)) = (&config_pathspec, &backed_up_files) {
+        let _timer = profile::start("commit configuration");
+        GIT(&[
+            "add",
+            "--",
+            pathspec,
+            &format!(":(exclude){pathspec}/{LOG_FILE}"),
+        ])?;
+        GIT(&[
+            "commit",
+            "--message",
+            &format!("{APP_NAME}: Restore configuration files"),
+        ])?;
+    }
+
+    emit(Event::Summary {
+        merged: merged_pull_requests
+            .iter()
+            .map(|pull_request| pull_request.number.as_str())
+            .collect(),
+        failed: &failed_pull_requests,
+        retries: &reports.retries,
+    });
+
+    for (pull_request, retries) in &reports.retries {
+        info!(
+            "Retried fetching pull request {}{} {retries} time{}",
+            "#".bright_blue(),
+            pull_request.bright_blue(),
+            if *retries == 1 { "" } else { "s" }
+        );
+    }
+
+    // patches which are applied to an earlier run are already on top of its orphan history
+    let (head, base) = if config.orphan && !only_patches {
+        let _timer = profile::start("orphan history");
+        let copies = copy_onto_orphan(
+            &run_start,
+            "HEAD",
+            &format!(
+                "{APP_NAME}: Snapshot of {} {remote_branch}\n\nUpstream commit: {run_start}",
+                config.repo
+            ),
+        )?;
+        let copy_of = |commit: &str| {
+            copies
+                .iter()
+                .find(|(original, _)| original == commit)
+                .map(|(_, copy)| copy.clone())
+        };
+
+        let head = copy_of(&GIT(&["rev-parse", "HEAD"])?)
+            .ok_or_else(|| anyhow!("Could not copy the commits of the run onto a new history"))?;
+        let base = copy_of(&base).unwrap_or(base);
+
+        info!(
+            "{} {} will not have the history of {remote_branch}, because of the `orphan` option. Its first commit is a snapshot of the files of {remote_branch}",
+            "Warning:".bright_yellow().bold(),
+            config.local_branch.cyan(),
+        );
+
+        (head, base)
+    } else {
+        ("HEAD".to_owned(), base)
+    };
 
     let temporary_branch = with_uuid("temp-branch");
 
-    GIT(&["switch", "--create", &temporary_branch])?;
+    GIT(&["switch", "--create", &temporary_branch, &head])?;
 
-    clean_up_remote(
-        &info.remote.local_remote_alias,
-        &info.branch.local_branch_name,
-    )?;
+    clean_up()?;
 
     if has_yes_flag
         || confirm_prompt!(
@@ -261,11 +1496,41 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
         // WARNING: this is a destructive action which erases the original branch
         GIT(&[
             "branch",
-            "--move",
+            if config.keep_temp_branch {
+                "--copy"
+            } else {
+                "--move"
+            },
             "--force",
             &temporary_branch,
             &config.local_branch,
         ])?;
+
+        if config.keep_temp_branch {
+            GIT(&["switch", &config.local_branch])?;
+
+            let created_at = format_timestamp(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or_default(),
+            );
+            let kept_branch = format!(
+                "{APP_NAME}-temp/{}-{}",
+                config.local_branch,
+                created_at
+                    .trim_end_matches(" UTC")
+                    .replace(' ', "_")
+                    .replace(':', "-")
+            );
+
+            GIT(&["branch", "--move", &temporary_branch, &kept_branch])?;
+
+            info!(
+                "Kept the temporary branch as {} because of the `keep-temp-branch` option",
+                kept_branch.cyan()
+            );
+        }
         if has_yes_flag {
             info!(
                 "Overwrote branch {} since you supplied the {} flag",
@@ -273,18 +1538,62 @@ pub async fn run(args: &CommandArgs) -> anyhow::Result<()> {
                 "--yes".bright_magenta()
             );
         }
-        println!("\n{INDENT}{}", "  Success!\n".bright_green().bold());
+
+        let log_entry = RunLogEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            config_hash: GIT(&[
+                "hash-object",
+                "--",
+                config_file_path.to_str().unwrap_or_default(),
+            ])?,
+            sha: GIT(&["rev-parse", &config.local_branch])?,
+            base: Some(base),
+            pull_requests: merged_pull_requests,
+        };
+
+        if let Err(err) = append_run_log(&log_entry) {
+            fail!("Could not record this run in {CONFIG_ROOT}/{LOG_FILE}\n{err}");
+        }
+
+        if RUN_VERIFY_CLEAN_AFTER_FLAG.is_in_args(args) {
+            // the log is expected to be untracked
+            let mut status_args = vec!["status", "--porcelain", "--untracked-files=all"];
+            let log_pathspec = config_pathspec
+                .as_ref()
+                .map(|pathspec| format!(":(exclude){pathspec}/{LOG_FILE}"));
+            if let Some(ref log_pathspec) = log_pathspec {
+                status_args.extend(["--", log_pathspec]);
+            }
+
+            let dirty_files = GIT(&status_args)?;
+
+            if !dirty_files.is_empty() {
+                return Err(anyhow!(
+                    "The working tree is not clean after the run, which is probably a bug in {APP_NAME}. Changed files:\n{dirty_files}"
+                ));
+            }
+
+            trace!("The working tree is clean after the run");
+        }
+
+        eprintln!("\n{INDENT}{}", "  Success!\n".bright_green().bold());
     } else {
         let command = format!(
             "  git branch --move --force {temporary_branch} {}",
             config.local_branch
         );
         let command = format!("\n{INDENT}{}\n", command.bright_magenta());
-        println!(
-            "\n{INDENT}  You can still manually overwrite {} with the following command:\n  {command}",
-            config.local_branch.cyan(),
+        eprintln!(
+            "{}",
+            redact(&format!(
+                "\n{INDENT}  You can still manually overwrite {} with the following command:\n  {command}",
+                config.local_branch.cyan(),
+            ))
         );
-        std::process::exit(1)
+        return Err(anyhow!("Did not overwrite {}", config.local_branch));
     }
 
     Ok(())
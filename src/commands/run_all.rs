@@ -0,0 +1,175 @@
+use std::{
+    env, fs,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::anyhow;
+use colored::Colorize;
+use futures::{stream, StreamExt};
+use tokio::process::Command;
+
+use crate::{
+    commands::{
+        help,
+        help::{CONFIG_FLAG, CONFIG_ROOT_FLAG},
+        run::{RUN_WATCH_FLAG, RUN_YES_FLAG},
+    },
+    fail,
+    flags::THREADS,
+    info, success,
+    types::CommandArgs,
+    APP_NAME, CONFIG_FILE, CONFIG_ROOT, INDENT,
+};
+
+/// Finds every directory below `dir` which has a config, without looking inside of the repositories it finds
+fn find_configs(dir: &Path, configs: &mut Vec<PathBuf>) {
+    if dir.join(CONFIG_ROOT).join(CONFIG_FILE).is_file() {
+        configs.push(dir.to_path_buf());
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut subdirs: Vec<_> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        // e.g. `.git`
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    subdirs.sort();
+
+    for subdir in subdirs {
+        find_configs(&subdir, configs);
+    }
+}
+
+/// Runs `patchy run` for every config found in the given directories. Each one is in a different repository,
+/// so they are run at the same time as separate processes
+pub async fn run_all(args: &CommandArgs) -> anyhow::Result<()> {
+    let dirs: Vec<_> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    if dirs.is_empty() {
+        fail!("You haven't specified any directories to look for configs in");
+        return help(Some("run-all"));
+    }
+
+    if RUN_WATCH_FLAG.is_in_args(args) {
+        return Err(anyhow!(
+            "The {} flag can't be used with run-all",
+            RUN_WATCH_FLAG.long.bright_magenta()
+        ));
+    }
+
+    // the runs happen at the same time, so they can't ask which branches may be overwritten
+    if !RUN_YES_FLAG.is_in_args(args) {
+        return Err(anyhow!(
+            "run-all can't ask before overwriting each config's local-branch, pass the {} flag to allow it",
+            RUN_YES_FLAG.long.bright_magenta()
+        ));
+    }
+
+    let mut configs = vec![];
+    for dir in dirs {
+        if !Path::new(dir).is_dir() {
+            return Err(anyhow!("{dir} is not a directory"));
+        }
+        find_configs(Path::new(dir), &mut configs);
+    }
+
+    if configs.is_empty() {
+        info!("Could not find any {CONFIG_ROOT}/{CONFIG_FILE} in the given directories");
+        return Ok(());
+    }
+
+    info!(
+        "Found {} configs, running them with up to {} at the same time",
+        configs.len().to_string().bright_blue(),
+        THREADS.to_string().bright_blue()
+    );
+
+    // each run finds its own config
+    let run_args: Vec<_> = args
+        .iter()
+        .filter(|arg| {
+            arg.starts_with('-')
+                && ![&CONFIG_FLAG, &CONFIG_ROOT_FLAG]
+                    .iter()
+                    .any(|flag| arg.starts_with(flag.short) || arg.starts_with(flag.long))
+        })
+        .collect();
+
+    let patchy = env::current_exe()?;
+    let is_terminal = std::io::stderr().is_terminal();
+
+    let succeeded: Vec<_> = stream::iter(&configs)
+        .map(|dir| {
+            let mut command = Command::new(&patchy);
+            command
+                .arg("run")
+                .args(&run_args)
+                .current_dir(dir)
+                .stdin(Stdio::null());
+
+            if is_terminal {
+                command.env("CLICOLOR_FORCE", "1");
+            }
+
+            async move {
+                match command.output().await {
+                    Ok(output) => {
+                        // the output of a run is shown all at once, so that it doesn't mix with the others
+                        eprintln!(
+                            "\n{} {}\n{}\n",
+                            format!("{APP_NAME} run").bright_black(),
+                            dir.display().to_string().bright_cyan(),
+                            format!(
+                                "{}{}",
+                                String::from_utf8_lossy(&output.stdout),
+                                String::from_utf8_lossy(&output.stderr)
+                            )
+                            .trim_end()
+                        );
+                        output.status.success()
+                    }
+                    Err(err) => {
+                        fail!("Could not run {APP_NAME} in {}\n{err}", dir.display());
+                        false
+                    }
+                }
+            }
+        })
+        .buffered(*THREADS)
+        .collect()
+        .await;
+
+    let failed: Vec<_> = configs
+        .iter()
+        .zip(&succeeded)
+        .filter(|(_, succeeded)| !**succeeded)
+        .map(|(dir, _)| dir)
+        .collect();
+
+    if failed.is_empty() {
+        success!(
+            "All {} runs succeeded",
+            configs.len().to_string().bright_blue()
+        );
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "{} of {} runs failed:\n{}",
+        failed.len().to_string().bright_red(),
+        configs.len(),
+        failed
+            .iter()
+            .map(|dir| format!("{INDENT}{}", dir.display().to_string().bright_cyan()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    ))
+}
@@ -0,0 +1,179 @@
+use std::fs;
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::{pr_fetch::ignore_octothorpe, run::parse_if_maybe_hash},
+    git_commands::{request_default_branch, request_pull_request_list, CONFIG_LOCATION},
+    info, success,
+    types::{CommandArgs, Configuration, PullRequestState},
+    CONFIG_FILE, CONFIG_ROOT, INDENT,
+};
+
+/// Keeps the selected pull requests which are already in the config as they are, including their
+/// commit hashes and options, and adds the other selected ones after them
+fn replace_pull_requests(config_raw: &str, selected: &[String]) -> anyhow::Result<String> {
+    let mut document = config_raw
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|err| {
+            anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+        })?;
+
+    let config_pull_requests = document
+        .entry("pull-requests")
+        .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("`pull-requests` in {CONFIG_ROOT}/{CONFIG_FILE} is not a list"))?;
+
+    let mut kept = vec![];
+    let prefix =
+        |value: Option<&toml_edit::Value>| value.and_then(|value| value.decor().prefix()).cloned();
+    // so that the pull requests stay on separate lines if they are
+    let first_prefix = prefix(config_pull_requests.get(0));
+    let last_prefix = prefix(config_pull_requests.iter().last());
+
+    config_pull_requests.retain(|value| {
+        let pull_request = match value.as_inline_table() {
+            Some(table) => table.get("id").and_then(|id| id.as_str()),
+            None => value.as_str(),
+        };
+
+        let Some(pull_request) = pull_request else {
+            return true;
+        };
+
+        let (pull_request, _) = parse_if_maybe_hash(&ignore_octothorpe(pull_request), " @ ");
+        let is_selected = selected.contains(&pull_request);
+
+        if is_selected {
+            kept.push(pull_request);
+        }
+
+        is_selected
+    });
+
+    for pull_request in selected {
+        if !kept.contains(pull_request) {
+            let mut value = toml_edit::Value::from(pull_request.as_str());
+            if let Some(ref prefix) = last_prefix {
+                value.decor_mut().set_prefix(prefix.clone());
+            }
+            config_pull_requests.push_formatted(value);
+        }
+    }
+
+    // the first pull request may be removed, and the one after it would be separated from `[` by a space
+    if let (Some(first), Some(prefix)) = (config_pull_requests.get_mut(0), first_prefix) {
+        first.decor_mut().set_prefix(prefix);
+    }
+
+    Ok(document.to_string())
+}
+
+/// Lets the user pick the pull requests of the config from the open pull requests of upstream
+pub async fn select(_args: &CommandArgs) -> anyhow::Result<()> {
+    let config_raw = fs::read_to_string(&CONFIG_LOCATION.file).map_err(|err| {
+        anyhow!(
+            "Could not read configuration file at {}\n{err}",
+            CONFIG_LOCATION.file.to_string_lossy()
+        )
+    })?;
+
+    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+        anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+    })?;
+
+    let client = reqwest::Client::new();
+
+    let remote_branch = match config.remote_branch {
+        Some(ref remote_branch) => remote_branch.clone(),
+        None => request_default_branch(&config.repo, &client).await?,
+    };
+    let (remote_branch, _) = parse_if_maybe_hash(&remote_branch, " @ ");
+
+    info!(
+        "Requesting the open pull requests of {} which target {}",
+        config.repo.bright_blue(),
+        remote_branch.cyan()
+    );
+
+    let open_pull_requests = request_pull_request_list(
+        &config.repo,
+        &remote_branch,
+        PullRequestState::Open,
+        &client,
+    )
+    .await?;
+
+    let configured: Vec<_> = config
+        .pull_requests
+        .iter()
+        .map(|pull_request| pull_request.number_and_commit().0)
+        .collect();
+
+    // pull requests of the config come first, even if they are closed or target another branch
+    let mut choices: Vec<(String, String)> = configured
+        .iter()
+        .map(|pull_request| {
+            let title = open_pull_requests
+                .iter()
+                .find(|open| &open.number == pull_request)
+                .map(|open| open.title.clone())
+                .unwrap_or_default();
+            (pull_request.clone(), title)
+        })
+        .collect();
+    choices.extend(
+        open_pull_requests
+            .into_iter()
+            .filter(|open| !configured.contains(&open.number))
+            .map(|open| (open.number, open.title)),
+    );
+
+    if choices.is_empty() {
+        info!("There are no open pull requests to select from");
+        return Ok(());
+    }
+
+    let items: Vec<_> = choices
+        .iter()
+        .map(|(pull_request, title)| format!("#{pull_request} {title}"))
+        .collect();
+    let defaults: Vec<_> = choices
+        .iter()
+        .map(|(pull_request, _)| configured.contains(pull_request))
+        .collect();
+
+    let Some(selection) = dialoguer::MultiSelect::new()
+        .with_prompt(format!(
+            "\n{INDENT}{} Select the pull requests to merge with space, and confirm with enter",
+            "»".bright_black()
+        ))
+        .items(&items)
+        .defaults(&defaults)
+        .interact_opt()
+        .map_err(|err| anyhow!("Could not show the list of pull requests\n{err}"))?
+    else {
+        info!("Cancelled, {CONFIG_ROOT}/{CONFIG_FILE} was not changed");
+        return Ok(());
+    };
+
+    let selected: Vec<_> = selection
+        .into_iter()
+        .map(|index| choices[index].0.clone())
+        .collect();
+
+    fs::write(
+        &CONFIG_LOCATION.file,
+        replace_pull_requests(&config_raw, &selected)?,
+    )?;
+
+    success!(
+        "Wrote {} pull request{} to {CONFIG_ROOT}/{CONFIG_FILE}",
+        selected.len(),
+        if selected.len() == 1 { "" } else { "s" }
+    );
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::{env, fs, path::Path, process::Command};
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    fail,
+    git_commands::{get_git_output, spawn_git_with_env},
+    info, success, APP_NAME, CONFIG_FILE, CONFIG_ROOT, LOG_FILE,
+};
+
+/// The temporary repositories may be created where git has no identity configured
+static IDENTITY: &[(&str, &str)] = &[
+    ("GIT_AUTHOR_NAME", APP_NAME),
+    ("GIT_AUTHOR_EMAIL", "selftest@patchy.invalid"),
+    ("GIT_COMMITTER_NAME", APP_NAME),
+    ("GIT_COMMITTER_EMAIL", "selftest@patchy.invalid"),
+];
+
+static LOCAL_BRANCH: &str = "patchy-selftest";
+
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    get_git_output(spawn_git_with_env(args, dir, IDENTITY)?, args)
+}
+
+/// Upstream has a pull request at `refs/pull/1/head`, like a mirror of a GitHub repository
+fn create_upstream(upstream: &Path) -> anyhow::Result<String> {
+    fs::create_dir_all(upstream)?;
+    git(upstream, &["init", "--quiet", "--initial-branch=main"])?;
+    fs::write(upstream.join("upstream.txt"), "upstream\n")?;
+    git(upstream, &["add", "."])?;
+    git(upstream, &["commit", "--quiet", "--message=upstream"])?;
+
+    git(upstream, &["switch", "--quiet", "--create", "pull-request"])?;
+    fs::write(upstream.join("pull-request.txt"), "pull request\n")?;
+    git(upstream, &["add", "."])?;
+    git(upstream, &["commit", "--quiet", "--message=pull request"])?;
+    git(upstream, &["update-ref", "refs/pull/1/head", "HEAD"])?;
+
+    // the patch is a change on top of upstream which has no pull request
+    git(
+        upstream,
+        &["switch", "--quiet", "--create", "patch", "main"],
+    )?;
+    fs::write(upstream.join("patch.txt"), "patch\n")?;
+    git(upstream, &["add", "."])?;
+    git(upstream, &["commit", "--quiet", "--message=patch"])?;
+    let patch = git(upstream, &["format-patch", "-1", "--stdout"])?;
+
+    git(upstream, &["switch", "--quiet", "main"])?;
+
+    Ok(patch)
+}
+
+/// The fork tracks its config, and already has a `local-branch` from an earlier run which gets overwritten
+fn create_fork(fork: &Path, upstream: &Path, patch: &str) -> anyhow::Result<String> {
+    let config_root = fork.join(CONFIG_ROOT);
+    fs::create_dir_all(&config_root)?;
+    git(fork, &["init", "--quiet", "--initial-branch=main"])?;
+
+    let config = format!(
+        "repo = {:?}\nremote-branch = \"main\"\nlocal-branch = \"{LOCAL_BRANCH}\"\npull-requests = [\"1\"]\npatches = [\"selftest\"]\n",
+        upstream.to_string_lossy()
+    );
+    fs::write(config_root.join(CONFIG_FILE), &config)?;
+    fs::write(config_root.join("selftest.patch"), format!("{patch}\n"))?;
+    git(fork, &["add", "."])?;
+    git(fork, &["commit", "--quiet", "--message=config"])?;
+    git(fork, &["branch", LOCAL_BRANCH])?;
+
+    Ok(config)
+}
+
+/// Runs patchy on a throwaway fork of a throwaway upstream, and checks that the result is what it should be
+pub fn selftest() -> anyhow::Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("{APP_NAME}-selftest-"))
+        .tempdir()?;
+    let upstream = dir.path().join("upstream");
+    let fork = dir.path().join("fork");
+
+    info!("Creating a test repository in {}", dir.path().display());
+
+    let patch = create_upstream(&upstream)
+        .map_err(|err| anyhow!("Could not create the upstream repository\n{err}"))?;
+    let config = create_fork(&fork, &upstream, &patch)
+        .map_err(|err| anyhow!("Could not create the fork\n{err}"))?;
+    let main_before = git(&fork, &["rev-parse", "main"])?;
+
+    info!(
+        "Running {} in it",
+        format!("{APP_NAME} run --yes").bright_magenta()
+    );
+
+    let output = Command::new(env::current_exe()?)
+        .args(["run", "--yes"])
+        .envs(IDENTITY.iter().copied())
+        .current_dir(&fork)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "The run failed\nStdout: {}\nStderr: {}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let has_file = |file: &str| {
+        git(
+            &fork,
+            &["cat-file", "-e", &format!("{LOCAL_BRANCH}:{file}")],
+        )
+        .is_ok()
+    };
+    let config_path = format!("{LOCAL_BRANCH}:{CONFIG_ROOT}/{CONFIG_FILE}");
+
+    let checks = [
+        ("upstream is the base", has_file("upstream.txt")),
+        ("the pull request was merged", has_file("pull-request.txt")),
+        ("the patch was applied", has_file("patch.txt")),
+        (
+            "the config was restored",
+            git(&fork, &["show", &config_path]).is_ok_and(|restored| restored == config.trim_end()),
+        ),
+        (
+            "the branch which the run started on is unchanged",
+            git(&fork, &["rev-parse", "main"]).is_ok_and(|main| main == main_before),
+        ),
+        (
+            "local-branch is checked out, without uncommitted changes",
+            git(&fork, &["rev-parse", "--abbrev-ref", "HEAD"])
+                .is_ok_and(|branch| branch == LOCAL_BRANCH)
+                && git(&fork, &["status", "--porcelain", "--untracked-files=no"])
+                    .is_ok_and(|status| status.is_empty()),
+        ),
+        (
+            "the run was recorded in the log",
+            fork.join(CONFIG_ROOT).join(LOG_FILE).is_file(),
+        ),
+        (
+            "the temporary remotes were removed",
+            git(&fork, &["remote"]).is_ok_and(|remotes| remotes.is_empty()),
+        ),
+    ];
+
+    for (check, passed) in &checks {
+        if *passed {
+            success!("{check}");
+        } else {
+            fail!("{check}");
+        }
+    }
+
+    if checks.iter().all(|(_, passed)| *passed) {
+        success!("{APP_NAME} and git work in this environment");
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Some checks failed, so {APP_NAME} may not work correctly in this environment. The output of the run was:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::{
+        help,
+        run::{check_patches, parse_if_maybe_hash},
+    },
+    fail,
+    flags::{is_valid_flag, Flag},
+    git_commands::{git_in, repo_url, request_default_branch, CONFIG_LOCATION},
+    info,
+    types::{CommandArgs, Configuration},
+    APP_NAME, CONFIG_FILE, CONFIG_ROOT,
+};
+
+use super::help::{CONFIG_FLAG, CONFIG_ROOT_FLAG, HELP_FLAG, REDACT_FLAG, VERSION_FLAG};
+
+pub static TEST_PATCHES_UPSTREAM_REF_FLAG: Flag<'static> = Flag {
+    short: "-u=",
+    long: "--upstream-ref=",
+    description: "Test the patches against this branch, tag or commit of upstream, instead of `remote-branch`",
+};
+
+pub static TEST_PATCHES_FLAGS: &[&Flag<'static>; 6] = &[
+    &TEST_PATCHES_UPSTREAM_REF_FLAG,
+    &CONFIG_FLAG,
+    &CONFIG_ROOT_FLAG,
+    &HELP_FLAG,
+    &VERSION_FLAG,
+    &REDACT_FLAG,
+];
+
+/// Checks that every patch applies to a fresh checkout of upstream in a temporary directory,
+/// without touching the repository
+pub async fn test_patches(args: &CommandArgs) -> anyhow::Result<()> {
+    if let Some(arg) = args
+        .iter()
+        .find(|arg| arg.starts_with('-') && !is_valid_flag(arg, TEST_PATCHES_FLAGS))
+    {
+        fail!("Invalid flag: {arg}");
+        let _ = help(Some("test-patches"));
+        std::process::exit(1);
+    }
+
+    let config_raw = fs::read_to_string(&CONFIG_LOCATION.file).map_err(|err| {
+        anyhow!(
+            "Could not read configuration file at {}\n{err}",
+            CONFIG_LOCATION.file.to_string_lossy()
+        )
+    })?;
+
+    let config = toml::from_str::<Configuration>(&config_raw).map_err(|err| {
+        anyhow!("Could not parse `{CONFIG_ROOT}/{CONFIG_FILE}` configuration file:\n{err}")
+    })?;
+
+    let Some(patches) = config
+        .patches
+        .as_ref()
+        .filter(|patches| !patches.is_empty())
+    else {
+        info!("You haven't specified any patches to test in your config");
+        return Ok(());
+    };
+
+    let client = reqwest::Client::new();
+
+    let upstream_ref = match args
+        .iter()
+        .find_map(|arg| TEST_PATCHES_UPSTREAM_REF_FLAG.extract_from_arg(arg))
+    {
+        Some(upstream_ref) => upstream_ref,
+        None => {
+            let remote_branch = match config.remote_branch {
+                Some(ref remote_branch) => remote_branch.clone(),
+                None => request_default_branch(&config.repo, &client).await?,
+            };
+            // `<branch> @ <commit-hash>` is tested at the commit
+            match parse_if_maybe_hash(&remote_branch, " @ ") {
+                (_, Some(commit_hash)) => commit_hash,
+                (remote_branch, None) => remote_branch,
+            }
+        }
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("{APP_NAME}-test-patches-"))
+        .tempdir()?;
+    let checkout = dir.path().join("upstream");
+    fs::create_dir(&checkout)?;
+
+    info!(
+        "Fetching {} of {} into {}",
+        upstream_ref.bright_cyan(),
+        config.repo.bright_blue(),
+        checkout.display()
+    );
+
+    git_in(&checkout, &["init", "--quiet"])?;
+    git_in(
+        &checkout,
+        &[
+            "fetch",
+            "--quiet",
+            "--no-tags",
+            "--depth=1",
+            &repo_url(&config.repo),
+            &upstream_ref,
+        ],
+    )
+    .map_err(|err| anyhow!("Could not fetch {upstream_ref} of {}\n{err}", config.repo))?;
+    let base = git_in(&checkout, &["rev-parse", "FETCH_HEAD"])?;
+
+    // only the commit itself was fetched, without its history or tags
+    check_patches(patches, &checkout, &base, false, &upstream_ref, &client).await
+}
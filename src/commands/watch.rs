@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, fs, time::Duration};
+
+use colored::Colorize;
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::{
+    commands::run::{run, RUN_WATCH_FLAG},
+    fail,
+    git_commands::{CONFIG_LOCATION, GIT},
+    info, trace,
+    types::CommandArgs,
+    CONFIG_ROOT, LOG_FILE,
+};
+
+/// How long to wait for more changes before starting a run, since editors often write files in several steps
+static DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Contents of the config and the patches, which decide the outcome of a run
+fn snapshot_config_files() -> BTreeMap<String, Vec<u8>> {
+    fs::read_dir(&CONFIG_LOCATION.root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name() != LOG_FILE)
+        .filter_map(|entry| {
+            Some((
+                entry.file_name().to_string_lossy().into_owned(),
+                fs::read(entry.path()).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Files outside of the config which have uncommitted changes, since running would lose them
+fn dirty_files() -> anyhow::Result<String> {
+    let mut args = vec!["status", "--porcelain", "--untracked-files=no"];
+    let exclude_config = CONFIG_LOCATION
+        .pathspec()
+        .map(|pathspec| format!(":(exclude){pathspec}"));
+
+    if let Some(ref exclude_config) = exclude_config {
+        args.extend(["--", ".", exclude_config]);
+    }
+
+    GIT(&args)
+}
+
+/// Runs, and then runs again each time the config or the patches change, until interrupted with Ctrl-C
+pub async fn watch(args: &CommandArgs) -> anyhow::Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // only fails if we have stopped watching
+        let _ = sender.send(event);
+    })?;
+
+    watcher.watch(&CONFIG_LOCATION.root, RecursiveMode::NonRecursive)?;
+
+    // created once, so that pressing Ctrl-C during a run stops watching once the run is done
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+
+    let mut last_run = None;
+
+    loop {
+        let snapshot = snapshot_config_files();
+
+        // a run switches branches, which touches the files even though nothing changed
+        if last_run.as_ref() != Some(&snapshot) {
+            if last_run.is_some() {
+                eprintln!("\n{}", "─".repeat(60).bright_black());
+            }
+
+            match dirty_files() {
+                Ok(dirty_files) if !dirty_files.is_empty() => fail!(
+                    "Not running because these files have uncommitted changes:\n{dirty_files}"
+                ),
+                Ok(_) => {
+                    if let Err(err) = run(args).await {
+                        fail!("{err}");
+                    }
+                }
+                Err(err) => fail!("{err}"),
+            }
+
+            last_run = Some(snapshot_config_files());
+
+            // switching branches can remove the directory and create it again, which would end the watch
+            let _ = watcher.unwatch(&CONFIG_LOCATION.root);
+            watcher.watch(&CONFIG_LOCATION.root, RecursiveMode::NonRecursive)?;
+
+            info!(
+                "Watching {CONFIG_ROOT} for changes because of the {} flag, press Ctrl-C to stop",
+                RUN_WATCH_FLAG.long.bright_magenta()
+            );
+        }
+
+        tokio::select! {
+            event = receiver.recv() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+
+                if let Err(err) = event {
+                    fail!("Could not watch {CONFIG_ROOT} for changes\n{err}");
+                }
+            }
+            _ = &mut ctrl_c => {
+                info!("Stopped watching");
+                return Ok(());
+            }
+        }
+
+        // wait until the files stop changing
+        while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE, receiver.recv()).await {}
+
+        trace!("Files in {CONFIG_ROOT} changed");
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::{help, pr_fetch::ignore_octothorpe},
+    fail,
+    run_log::read_run_log,
+    success,
+    types::CommandArgs,
+    CONFIG_ROOT, LOG_FILE,
+};
+
+pub fn which(args: &CommandArgs) -> anyhow::Result<()> {
+    let Some(pull_request) = args.iter().find(|arg| !arg.starts_with('-')) else {
+        fail!("You haven't specified a pull request");
+        return help(Some("which"));
+    };
+
+    let pull_request = ignore_octothorpe(pull_request);
+
+    let Some(last_run) = read_run_log()?.pop() else {
+        return Err(anyhow!(
+            "No runs have been recorded in {CONFIG_ROOT}/{LOG_FILE} yet"
+        ));
+    };
+
+    let merged = last_run
+        .pull_requests
+        .iter()
+        .find(|merged| merged.number == pull_request)
+        .ok_or_else(|| {
+            anyhow!(
+                "Pull request #{pull_request} was not merged in the last run, which produced commit {}",
+                last_run.sha
+            )
+        })?;
+
+    // the branch it was fetched into only exists during the run
+    success!(
+        "Pull request {}{} was merged in commit {}",
+        "#".bright_blue(),
+        pull_request.bright_blue(),
+        merged.commit.bright_yellow()
+    );
+
+    Ok(())
+}
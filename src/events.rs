@@ -0,0 +1,69 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{flags::EMIT_EVENTS, profile::Phase, redact::redact};
+
+/// Progress events emitted as newline-delimited JSON to stdout with `--events=json`,
+/// so that frontends can display the progress of a run live
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    FetchStart {
+        pull_request: &'a str,
+    },
+    FetchDone {
+        pull_request: &'a str,
+    },
+    FetchFailed {
+        pull_request: &'a str,
+        error: String,
+    },
+    MergeStart {
+        pull_request: &'a str,
+    },
+    MergeDone {
+        pull_request: &'a str,
+    },
+    Conflict {
+        pull_request: &'a str,
+        error: String,
+    },
+    /// How long each phase of the run took, with `--profile`
+    Profile {
+        phases: &'a [Phase],
+    },
+    Summary {
+        merged: Vec<&'a str>,
+        failed: &'a [String],
+        /// How many times fetching each pull request was retried, if at all
+        retries: &'a BTreeMap<String, usize>,
+    },
+}
+
+#[derive(Serialize)]
+struct TimestampedEvent<'a> {
+    #[serde(flatten)]
+    event: Event<'a>,
+    /// Milliseconds since the UNIX epoch
+    timestamp: u128,
+}
+
+/// Writes the event to stdout, if events were requested
+pub fn emit(event: Event) {
+    if !*EMIT_EVENTS {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+
+    if let Ok(line) = serde_json::to_string(&TimestampedEvent { event, timestamp }) {
+        println!("{}", redact(&line));
+    }
+}
@@ -1,9 +1,16 @@
-use std::{env, fmt::Display};
+use std::{env, fmt::Display, num::NonZeroUsize, thread::available_parallelism};
 
 use colored::Colorize;
 use once_cell::sync::Lazy;
 
-use crate::{commands::help::format_description, types::CommandArgs};
+use crate::{
+    commands::{
+        help::{format_description, REDACT_FLAG, THREADS_FLAG},
+        run::{RUN_EVENTS_FLAG, RUN_PROFILE_FLAG},
+    },
+    fail,
+    types::CommandArgs,
+};
 
 pub struct Flag<'a> {
     pub short: &'a str,
@@ -76,3 +83,48 @@ pub static IS_VERBOSE: Lazy<bool> = Lazy::new(|| {
     let args: CommandArgs = env::args().collect();
     args.contains("--verbose")
 });
+
+/// Replaces anything private in the output with placeholders
+pub static REDACT: Lazy<bool> =
+    Lazy::new(|| env::args().any(|arg| arg == REDACT_FLAG.short || arg == REDACT_FLAG.long));
+
+/// Measure how long each phase of the run takes, and show it at the end
+pub static PROFILE: Lazy<bool> = Lazy::new(|| {
+    env::args().any(|arg| arg == RUN_PROFILE_FLAG.short || arg == RUN_PROFILE_FLAG.long)
+});
+
+/// How many pull requests to fetch and patches to generate at the same time.
+/// Defaults to the number of logical CPUs, `1` makes everything sequential
+pub static THREADS: Lazy<usize> = Lazy::new(|| {
+    let Some(threads) = env::args().find_map(|arg| THREADS_FLAG.extract_from_arg(&arg)) else {
+        return available_parallelism().map_or(1, NonZeroUsize::get);
+    };
+
+    match threads.parse::<usize>() {
+        Ok(threads) if threads > 0 => threads,
+        _ => {
+            fail!(
+                "Invalid value for {}: {threads}, expected a positive number",
+                THREADS_FLAG.long
+            );
+            std::process::exit(1)
+        }
+    }
+});
+
+/// Emit progress events as newline-delimited JSON to stdout. Human-readable output goes to stderr instead
+pub static EMIT_EVENTS: Lazy<bool> = Lazy::new(|| {
+    let Some(format) = env::args().find_map(|arg| RUN_EVENTS_FLAG.extract_from_arg(&arg)) else {
+        return false;
+    };
+
+    if format != "json" {
+        fail!(
+            "Invalid flag {}{format}, the only supported format is `json`",
+            RUN_EVENTS_FLAG.long
+        );
+        std::process::exit(1)
+    }
+
+    true
+});
@@ -1,18 +1,38 @@
-use crate::{fail, utils::display_link};
+use crate::{
+    commands::help::{CONFIG_FLAG, CONFIG_ROOT_FLAG},
+    fail,
+    utils::display_link,
+    CONFIG_FILE, CONFIG_ROOT,
+};
 use colored::Colorize;
 use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::Write,
     path::{Path, PathBuf},
     process::Output,
+    sync::RwLock,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tempfile::NamedTempFile;
 
 use anyhow::{anyhow, Result};
 use once_cell::sync::Lazy;
 use reqwest::Client;
 
 use crate::{
+    run_log::MergedPullRequest,
     trace,
-    types::{Branch, BranchAndRemote, GitHubResponse, Remote},
-    utils::{make_request, normalize_commit_msg, with_uuid},
+    types::{
+        Branch, BranchAndRemote, CherryPick, FetchFrom, GistResponse, GitHubResponse, Head,
+        ListedPullRequest, MergeOptions, PatchCondition, PullRequestListItem, PullRequestState,
+        Remote, Repo, RepoResponse,
+    },
+    utils::{
+        download_text, is_mbox_patch, is_patch, is_secret_name, make_request, normalize_commit_msg,
+        with_uuid,
+    },
+    APP_NAME,
 };
 
 pub fn is_valid_branch_name(branch_name: &str) -> bool {
@@ -24,11 +44,118 @@ pub fn is_valid_branch_name(branch_name: &str) -> bool {
 pub static GITHUB_REMOTE_PREFIX: &str = "git@github.com:";
 pub static GITHUB_REMOTE_SUFFIX: &str = ".git";
 
+/// Environment variables set on every child process, from the `env` table of the config.
+/// Replaced on each run, since the config can change between the runs of `run --watch`
+static CHILD_ENV: Lazy<RwLock<Vec<(String, String)>>> = Lazy::new(RwLock::default);
+
+/// The environment variables from the `env` table of the config of the current run
+pub fn child_env() -> Vec<(String, String)> {
+    CHILD_ENV.read().unwrap().clone()
+}
+
+pub fn set_child_env(env: &BTreeMap<String, String>) {
+    for (name, value) in env {
+        trace!(
+            "Setting environment variable {name}={}",
+            if is_secret_name(name) {
+                "<redacted>"
+            } else {
+                value
+            }
+        );
+    }
+
+    *CHILD_ENV.write().unwrap() = env.clone().into_iter().collect();
+}
+
 pub fn spawn_git(args: &[&str], git_dir: &Path) -> Result<Output, std::io::Error> {
+    spawn_git_with_env(args, git_dir, &[])
+}
+
+/// Like `GIT`, but in another repository than the one patchy is running in
+pub fn git_in(repo: &Path, args: &[&str]) -> Result<String> {
+    trace!("$ git -C {} {}", repo.display(), args.join(" "));
+    get_git_output(spawn_git(args, repo)?, args)
+}
+
+/// Exit code when git is not installed, the same one that shells use for commands which don't exist
+pub static GIT_NOT_FOUND_EXIT_CODE: i32 = 127;
+
+/// Explains how to install git, if the error is from trying to run it when it isn't installed
+///
+/// # Examples
+///
+/// ```rust
+/// use std::{io, process::Command};
+/// use patchy::git_commands::git_not_found_message;
+///
+/// let err = Command::new("patchy-test-git-which-does-not-exist").output().unwrap_err();
+/// assert!(git_not_found_message(&err)
+///     .unwrap()
+///     .starts_with("git is required but was not found on PATH"));
+///
+/// let err = io::Error::from(io::ErrorKind::PermissionDenied);
+/// assert_eq!(git_not_found_message(&err), None);
+/// ```
+pub fn git_not_found_message(err: &std::io::Error) -> Option<String> {
+    (err.kind() == std::io::ErrorKind::NotFound).then(|| {
+        format!("git is required but was not found on PATH. Install it with your package manager or from https://git-scm.com/downloads, then run {APP_NAME} again")
+    })
+}
+
+/// Checks that git can be run at all, which is done once before any command
+pub fn find_git() -> Option<String> {
+    std::process::Command::new("git")
+        .arg("--version")
+        .output()
+        .err()
+        .as_ref()
+        .and_then(git_not_found_message)
+}
+
+/// Like `spawn_git` but sets additional environment variables, only for this command
+///
+/// # Examples
+///
+/// Without git on `PATH`, the error explains how to install it
+///
+/// ```rust
+/// use std::path::Path;
+/// use patchy::git_commands::spawn_git_with_env;
+///
+/// let err = spawn_git_with_env(&["--version"], Path::new("."), &[("PATH", "")]).unwrap_err();
+/// assert!(err.to_string().starts_with("git is required but was not found on PATH"));
+///
+/// let err = spawn_git_with_env(&["status"], Path::new("/patchy-test-missing-directory"), &[]).unwrap_err();
+/// assert!(err.to_string().starts_with("Could not run git in /patchy-test-missing-directory"));
+/// ```
+pub fn spawn_git_with_env(
+    args: &[&str],
+    git_dir: &Path,
+    env: &[(&str, &str)],
+) -> Result<Output, std::io::Error> {
     std::process::Command::new("git")
         .args(args)
+        .envs(child_env())
+        .envs(env.iter().copied())
         .current_dir(git_dir)
         .output()
+        .map_err(|err| {
+            // a directory which doesn't exist is reported the same way as a missing git
+            if !git_dir.is_dir() {
+                std::io::Error::new(
+                    err.kind(),
+                    format!(
+                        "Could not run git in {}, since the directory does not exist",
+                        git_dir.display()
+                    ),
+                )
+            } else if let Some(message) = git_not_found_message(&err) {
+                std::io::Error::new(err.kind(), message)
+            } else {
+                err
+            }
+        })
 }
 
 pub fn get_git_output(output: Output, args: &[&str]) -> anyhow::Result<String> {
@@ -64,6 +191,95 @@ pub static GIT_ROOT: Lazy<PathBuf> = Lazy::new(|| match get_git_root() {
     }
 });
 
+/// Where the configuration file and the patches are
+#[derive(Debug)]
+pub struct ConfigLocation {
+    /// Directory which contains the patches and, unless overridden, the configuration file
+    pub root: PathBuf,
+    pub file: PathBuf,
+}
+
+impl ConfigLocation {
+    /// The config root relative to the git root, which can be used as a pathspec.
+    /// `None` if the config root is outside of the repository
+    pub fn pathspec(&self) -> Option<String> {
+        self.root
+            .strip_prefix(&*GIT_ROOT)
+            .ok()
+            .and_then(|path| path.to_str())
+            .map(|path| match path.replace('\\', "/") {
+                path if path.is_empty() => ".".into(),
+                path => path,
+            })
+    }
+}
+
+/// Like git finds `.git`, searches for a config upwards from `start`, stopping at the git root.
+/// If there isn't one, uses the config root at the git root
+pub fn discover_config_root(start: &Path, git_root: &Path) -> PathBuf {
+    for dir in start.ancestors() {
+        let config_root = dir.join(CONFIG_ROOT);
+
+        if config_root.join(CONFIG_FILE).is_file() {
+            return config_root;
+        }
+
+        if dir == git_root {
+            break;
+        }
+    }
+
+    git_root.join(CONFIG_ROOT)
+}
+
+pub static CONFIG_LOCATION: Lazy<ConfigLocation> = Lazy::new(|| {
+    let absolute = |path: String| {
+        let path = PathBuf::from(path);
+        path.canonicalize().unwrap_or_else(|_| {
+            std::env::current_dir()
+                .map(|current_dir| current_dir.join(&path))
+                .unwrap_or(path)
+        })
+    };
+
+    let config_root = env::args()
+        .find_map(|arg| CONFIG_ROOT_FLAG.extract_from_arg(&arg))
+        .map(absolute);
+    let config_file = env::args()
+        .find_map(|arg| CONFIG_FLAG.extract_from_arg(&arg))
+        .map(absolute);
+
+    let location = match (config_root, config_file) {
+        (Some(root), Some(file)) => ConfigLocation { root, file },
+        (Some(root), None) => ConfigLocation {
+            file: root.join(CONFIG_FILE),
+            root,
+        },
+        // patches are next to the config file
+        (None, Some(file)) => ConfigLocation {
+            root: file
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| GIT_ROOT.clone()),
+            file,
+        },
+        (None, None) => {
+            let current_dir = std::env::current_dir()
+                .and_then(|current_dir| current_dir.canonicalize())
+                .unwrap_or_else(|_| GIT_ROOT.clone());
+            let root = discover_config_root(&current_dir, &GIT_ROOT);
+            ConfigLocation {
+                file: root.join(CONFIG_FILE),
+                root,
+            }
+        }
+    };
+
+    trace!("Using config root {:?}", location.root);
+
+    location
+});
+
 type Git = Lazy<Box<dyn Fn(&[&str]) -> Result<String> + Send + Sync>>;
 
 pub static GIT: Git = Lazy::new(|| {
@@ -73,10 +289,11 @@ pub static GIT: Git = Lazy::new(|| {
     })
 });
 
-/// Fetches a branch of a remote into local. Optionally accepts a commit hash for versioning.
+/// Fetches a branch of a new remote into local, at the commit hash if there is one, and with only `depth` commits of its history if that is set
 pub fn add_remote_branch(
     info: &BranchAndRemote,
     commit_hash: &Option<String>,
+    depth: Option<u32>,
 ) -> anyhow::Result<()> {
     if let Err(err) = GIT(&[
         "remote",
@@ -94,14 +311,33 @@ pub fn add_remote_branch(
         &info.remote.local_remote_alias
     );
 
-    if let Err(err) = GIT(&[
-        "fetch",
-        &info.remote.repository_url,
-        &format!(
-            "{}:{}",
-            info.branch.upstream_branch_name, info.branch.local_branch_name
-        ),
-    ]) {
+    let refspec = format!(
+        "{}:{}",
+        info.branch.upstream_branch_name, info.branch.local_branch_name
+    );
+    let depth = depth.map(|depth| format!("--depth={depth}"));
+    let mut fetch_args = vec!["fetch"];
+    fetch_args.extend(depth.as_deref());
+    fetch_args.extend([info.remote.repository_url.as_str(), &refspec]);
+
+    let shallow_before = match depth {
+        Some(_) => shallow_commits()?,
+        None => vec![],
+    };
+
+    let fetched = GIT(&fetch_args);
+
+    // the repository is shallow for these commits now, which is not worth warning about in later runs
+    if depth.is_some() {
+        for commit in shallow_commits()? {
+            if !shallow_before.contains(&commit) {
+                GIT(&["config", "--add", DEPTH_SHALLOW_KEY, &commit])?;
+            }
+        }
+    }
+
+    if let Err(err) = fetched {
+        GIT(&["remote", "remove", &info.remote.local_remote_alias])?;
         return Err(anyhow!(
             "We couldn't find branch {} of GitHub repository {}. Are you sure it \
                      exists?\n{err}",
@@ -118,20 +354,24 @@ pub fn add_remote_branch(
     );
 
     if let Some(commit_hash) = commit_hash {
-        GIT(&[
+        if let Err(err) = GIT(&[
             "branch",
             "--force",
             &info.branch.local_branch_name,
             commit_hash,
-        ])
-        .map_err(|err| {
-            anyhow!(
+        ]) {
+            // so that the branch of the pull request can be fetched into the same remote and branch instead
+            clean_up_remote(
+                &info.remote.local_remote_alias,
+                &info.branch.local_branch_name,
+            )?;
+            return Err(anyhow!(
                 "We couldn't find commit {} \
                                 of branch {}. Are you sure it exists?\n{err}",
                 commit_hash,
                 info.branch.local_branch_name
-            )
-        })?;
+            ));
+        }
 
         trace!("...and did a hard reset to commit {commit_hash}",);
     };
@@ -139,6 +379,205 @@ pub fn add_remote_branch(
     Ok(())
 }
 
+pub static GIST_PREFIX: &str = "gist:";
+
+/// Downloads the patch file of a GitHub Gist.
+///
+/// If the gist contains several files, the first one ending in `.patch` or `.diff` is used
+pub async fn request_gist_patch(gist_id: &str, client: &Client) -> anyhow::Result<String> {
+    let url = format!("https://api.github.com/gists/{gist_id}");
+
+    let response: GistResponse = make_request(client, &url)
+        .await
+        .map_err(|err| anyhow!("Could not fetch gist {gist_id}\n{err}\n"))?;
+
+    let file = response
+        .files
+        .values()
+        .find(|file| file.filename.ends_with(".patch") || file.filename.ends_with(".diff"))
+        .or_else(|| response.files.values().next())
+        .ok_or_else(|| anyhow!("Gist {gist_id} does not contain any files"))?;
+
+    let content = match file.content {
+        Some(ref content) if !file.truncated => content.clone(),
+        // large files are not included in the response in full
+        _ => download_text(client, &file.raw_url).await?,
+    };
+
+    if !is_patch(&content) {
+        return Err(anyhow!(
+            "File {} of gist {gist_id} is not a patch",
+            file.filename
+        ));
+    }
+
+    Ok(content)
+}
+
+/// Arguments for `git am`, used for applying patches
+pub fn am_args(preserve_dates: bool, signoff: bool) -> Vec<&'static str> {
+    let mut args = vec!["am", "--keep-cr"];
+
+    if signoff {
+        args.push("--signoff");
+    }
+
+    if preserve_dates {
+        args.push("--committer-date-is-author-date");
+    }
+
+    args
+}
+
+/// Applies the patch as a new commit, keeping the commit message if the patch has one
+pub fn apply_patch_text(
+    patch: &str,
+    name: &str,
+    preserve_dates: bool,
+    signoff: bool,
+) -> anyhow::Result<()> {
+    let mut patch_file = NamedTempFile::new()?;
+    write!(patch_file, "{patch}")?;
+    let patch_path = patch_file.path().to_str().unwrap_or_default();
+
+    if is_mbox_patch(patch) {
+        let mut args = am_args(preserve_dates, signoff);
+        args.push(patch_path);
+
+        if let Err(err) = GIT(&args) {
+            GIT(&["am", "--abort"])?;
+            return Err(err);
+        }
+    } else {
+        GIT(&["apply", "--index", patch_path])?;
+
+        let message = format!("patchy: Apply patch {name}");
+        let mut commit_args = vec!["commit", "--message", &message];
+        if signoff {
+            commit_args.push("--signoff");
+        }
+        GIT(&commit_args)?;
+    }
+
+    Ok(())
+}
+
+/// Checks a patch's `applies-if` condition against the commit that the patch would be applied to
+///
+/// Returns the reason why the patch should be skipped, if any
+pub fn unmet_patch_condition(
+    repo: &Path,
+    condition: &PatchCondition,
+    base: &str,
+) -> Option<String> {
+    if let Some(ref min_tag) = condition.min_tag {
+        if git_in(
+            repo,
+            &["rev-parse", "--verify", &format!("{min_tag}^{{commit}}")],
+        )
+        .is_err()
+        {
+            return Some(format!("tag {min_tag} does not exist"));
+        }
+
+        if git_in(repo, &["merge-base", "--is-ancestor", min_tag, base]).is_err() {
+            return Some(format!("upstream is older than tag {min_tag}"));
+        }
+    }
+
+    match (&condition.file, &condition.contains) {
+        (Some(file), contains) => {
+            let Ok(file_contents) = git_in(repo, &["cat-file", "-p", &format!("{base}:{file}")])
+            else {
+                return Some(format!("file {file} does not exist"));
+            };
+
+            if let Some(contains) = contains {
+                if !file_contents.contains(contains.as_str()) {
+                    return Some(format!("file {file} does not contain {contains:?}"));
+                }
+            }
+        }
+        (None, Some(_)) => {
+            return Some("`contains` was specified without a `file` to search in".into());
+        }
+        (None, None) => (),
+    }
+
+    None
+}
+
+/// Fills a separate index file with the tree of `commit`, so patches can be checked against it
+pub fn read_tree_into_index(repo: &Path, commit: &str, index: &Path) -> anyhow::Result<()> {
+    let args = ["read-tree", commit];
+    let index = index.to_string_lossy();
+
+    get_git_output(
+        spawn_git_with_env(&args, repo, &[("GIT_INDEX_FILE", &index)])?,
+        &args,
+    )?;
+
+    Ok(())
+}
+
+/// Applies the patch to a separate index file, leaving the working tree and the real index alone
+///
+/// Patches are checked one after another, so later patches can depend on earlier ones.
+/// If the patch does not apply, the error contains the hunk which failed
+pub fn check_patch(repo: &Path, patch: &Path, index: &Path) -> anyhow::Result<()> {
+    let patch = patch.to_string_lossy();
+    let args = ["apply", "--cached", "--verbose", &patch];
+    let index = index.to_string_lossy();
+
+    trace!("$ git {}", args.join(" "));
+
+    let output = spawn_git_with_env(&args, repo, &[("GIT_INDEX_FILE", &index)])?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ))
+    }
+}
+
+/// Commits of pull requests which were fetched with `--depth`, whose history is cut off because of that
+static DEPTH_SHALLOW_KEY: &str = "patchy.depth-shallow";
+
+/// Commits whose parents were not fetched, which git lists in `.git/shallow`
+fn shallow_commits() -> anyhow::Result<Vec<String>> {
+    let path = GIT_ROOT.join(GIT(&["rev-parse", "--git-path", "shallow"])?);
+
+    Ok(fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// Shallow clones are missing the history which merging, `min-tag` conditions and `patchy freeze` rely on.
+///
+/// Fetching a pull request with `--depth` makes the repository shallow too, but only for the commits of
+/// that pull request, which are not needed after the run. Those don't count
+pub fn is_shallow_repository() -> anyhow::Result<bool> {
+    if GIT(&["rev-parse", "--is-shallow-repository"])? != "true" {
+        return Ok(false);
+    }
+
+    let fetched_with_depth = GIT(&["config", "--get-all", DEPTH_SHALLOW_KEY]).unwrap_or_default();
+
+    Ok(shallow_commits()?
+        .iter()
+        .any(|commit| !fetched_with_depth.lines().any(|fetched| fetched == commit)))
+}
+
+/// Forgets the commits that were fetched with `--depth`, once the repository is not shallow anymore
+pub fn forget_depth_shallow_commits() {
+    let _ = GIT(&["config", "--unset-all", DEPTH_SHALLOW_KEY]);
+}
+
 /// Removes a remote and its branch
 pub fn clean_up_remote(remote: &str, branch: &str) -> anyhow::Result<()> {
     // NOTE: Caller needs to ensure this function only runs if the script created the branch or if the user gave explicit permission
@@ -147,8 +586,37 @@ pub fn clean_up_remote(remote: &str, branch: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// What to check out to get back to where we are now: the current branch, or the commit if HEAD is detached
+///
+/// # Examples
+///
+/// ```rust
+/// # use patchy::test_repo::{commit, temp_repo};
+/// # let _repo = temp_repo();
+/// # commit("first");
+/// # commit("second");
+/// use patchy::git_commands::{current_branch_or_commit, GIT};
+///
+/// assert_eq!(current_branch_or_commit().unwrap(), "main");
+///
+/// GIT(&["checkout", "--detach", "main~1"]).unwrap();
+/// let detached_at = GIT(&["rev-parse", "HEAD"]).unwrap();
+///
+/// assert_eq!(current_branch_or_commit().unwrap(), detached_at);
+/// ```
+pub fn current_branch_or_commit() -> anyhow::Result<String> {
+    let current_branch = GIT(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+
+    // `git checkout HEAD` would do nothing, and leave us wherever the failed run was
+    if current_branch == "HEAD" {
+        GIT(&["rev-parse", "HEAD"])
+    } else {
+        Ok(current_branch)
+    }
+}
+
 pub fn checkout_from_remote(branch: &str, remote: &str) -> anyhow::Result<String> {
-    let current_branch = GIT(&["rev-parse", "--abbrev-ref", "HEAD"]).or_else(|err| {
+    let current_branch = current_branch_or_commit().or_else(|err| {
         clean_up_remote(remote, branch)?;
         Err(anyhow!(
             "Couldn't get the current branch. This usually happens \
@@ -166,26 +634,226 @@ pub fn checkout_from_remote(branch: &str, remote: &str) -> anyhow::Result<String
     Ok(current_branch)
 }
 
+/// Squashes the branch into a single commit on top of the current branch
+///
+/// # Examples
+///
+/// With `preserve_dates`, the commit has the same dates as the last commit of the merged branch
+///
+/// ```rust
+/// # use patchy::test_repo::{git, git_with_env, temp_repo};
+/// # let _repo = temp_repo();
+/// # let date = "2021-06-15T12:00:00Z";
+/// # git(&["commit", "--quiet", "--allow-empty", "--message=base"]);
+/// # git(&["switch", "--quiet", "--create", "feature"]);
+/// # std::fs::write("file", "change").unwrap();
+/// # git(&["add", "file"]);
+/// # git_with_env(&["commit", "--quiet", "--message=feature"], &[("GIT_AUTHOR_DATE", date), ("GIT_COMMITTER_DATE", date)]);
+/// # git(&["switch", "--quiet", "main"]);
+/// use patchy::{git_commands::{merge_into_main, GIT}, types::MergeOptions};
+///
+/// let options = MergeOptions { preserve_dates: true, ..Default::default() };
+///
+/// merge_into_main("feature", "feature", &options).unwrap();
+///
+/// let dates = GIT(&["log", "-1", "--format=%aI %cI"]).unwrap();
+/// assert_eq!(dates, "2021-06-15T12:00:00Z 2021-06-15T12:00:00Z".replace('Z', "+00:00"));
+/// ```
+///
+/// With `cherry_pick`, only the changes of the commits at the end of the branch are applied. For a merge commit,
+/// those are the changes it made to the branch that it was merged into
+///
+/// ```rust
+/// # use patchy::test_repo::{commit, git, temp_repo};
+/// # let _repo = temp_repo();
+/// # git(&["commit", "--quiet", "--allow-empty", "--message=base"]);
+/// # git(&["switch", "--quiet", "--create", "feature"]);
+/// # commit("feature");
+/// # git(&["switch", "--quiet", "--create", "upstream", "main"]);
+/// # commit("upstream");
+/// # git(&["merge", "--quiet", "--no-ff", "--message=merge feature", "feature"]);
+/// # git(&["switch", "--quiet", "main"]);
+/// use patchy::{git_commands::{merge_into_main, GIT}, types::{CherryPick, MergeOptions}};
+///
+/// // `upstream` has a commit of its own, and then merges `feature`
+/// let options = MergeOptions { cherry_pick: Some(CherryPick::Merge), ..Default::default() };
+///
+/// merge_into_main("upstream", "upstream", &options).unwrap();
+///
+/// let files = GIT(&["ls-tree", "--name-only", "HEAD"]).unwrap();
+/// assert_eq!(files, "feature");
+/// ```
 pub fn merge_into_main(
     local_branch: &str,
     remote_branch: &str,
+    options: &MergeOptions<'_>,
 ) -> anyhow::Result<String, anyhow::Error> {
     trace!("Merging branch {local_branch}");
 
-    if let Err(err) = GIT(&["merge", "--squash", local_branch]) {
-        // nukes the worktree
-        GIT(&["reset", "--hard"])?;
-        return Err(anyhow!("Could not merge {remote_branch}\n{err}"));
+    let merged = match options.cherry_pick {
+        None => GIT(&["merge", "--squash", local_branch]),
+        Some(CherryPick::Merge) => {
+            GIT(&["cherry-pick", "--no-commit", "--mainline=1", local_branch])
+        }
+        Some(CherryPick::Commit) => GIT(&["cherry-pick", "--no-commit", local_branch]),
+        Some(CherryPick::Rebased { commits }) => GIT(&[
+            "cherry-pick",
+            "--no-commit",
+            &format!("{local_branch}~{commits}..{local_branch}"),
+        ]),
+    };
+
+    if let Err(err) = merged {
+        if options.cherry_pick.is_some() {
+            // keeps the changes and conflicts, but another cherry-pick could not start while this one is in progress
+            let _ = GIT(&["cherry-pick", "--quit"]);
+        }
+
+        // the worktree has to be reset even if the conflicts can't be described
+        let has_conflicts = GIT(&["ls-files", "--unmerged"]).is_ok_and(|files| !files.is_empty());
+
+        let conflicts = if has_conflicts {
+            describe_conflicts(options.merged_before).unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        if !(options.leave_conflicts && has_conflicts) {
+            // nukes the worktree
+            GIT(&["reset", "--hard"])?;
+        }
+        return Err(anyhow!("Could not merge {remote_branch}\n{conflicts}{err}"));
+    };
+
+    commit_squash_merge(local_branch, options)?;
+
+    Ok(format!("Merged {remote_branch} successfully"))
+}
+
+/// Lists the conflicting files of a merge, with the pull requests merged before it which also changed them.
+/// Resolving the conflicts of each pull request separately could give inconsistent results for those files
+fn describe_conflicts(merged_before: &[MergedPullRequest]) -> anyhow::Result<String> {
+    let changed_files = merged_before
+        .iter()
+        .map(|merged| {
+            GIT(&[
+                "diff-tree",
+                "--no-commit-id",
+                "--name-only",
+                "-r",
+                &merged.commit,
+            ])
+            .map(|files| (&merged.number, files))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut conflicts = String::new();
+
+    for file in GIT(&["diff", "--name-only", "--diff-filter=U"])?.lines() {
+        let changed_by: Vec<_> = changed_files
+            .iter()
+            .filter(|(_, files)| files.lines().any(|changed| changed == file))
+            .map(|(number, _)| format!("#{number}"))
+            .collect();
+
+        if changed_by.is_empty() {
+            conflicts.push_str(&format!("Conflict in {file}\n"));
+        } else {
+            conflicts.push_str(&format!(
+                "Conflict in {file}, which was also changed by pull request{} {}\n",
+                if changed_by.len() == 1 { "" } else { "s" },
+                changed_by.join(", ")
+            ));
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Commits the changes of a pull request which were merged with `git merge --squash`
+pub fn commit_squash_merge(local_branch: &str, options: &MergeOptions<'_>) -> anyhow::Result<()> {
+    let message = format!("patchy: Merge {local_branch}");
+    let mut commit_args = vec!["commit", "--message", &message];
+
+    if options.signoff {
+        commit_args.push("--signoff");
+    }
+
+    // git formats them so that `git interpret-trailers` can parse them later
+    for trailer in options.trailers {
+        commit_args.extend(["--trailer", trailer]);
+    }
+
+    let dates = if options.preserve_dates {
+        let dates = GIT(&["log", "-1", "--format=%aI%n%cI", local_branch])?;
+        let (author_date, committer_date) = dates.split_once('\n').unwrap_or((&dates, &dates));
+        vec![
+            ("GIT_AUTHOR_DATE", author_date.to_owned()),
+            ("GIT_COMMITTER_DATE", committer_date.to_owned()),
+        ]
+    } else {
+        vec![]
     };
+    let dates: Vec<_> = dates
+        .iter()
+        .map(|(name, date)| (*name, date.as_str()))
+        .collect();
 
     // --squash will NOT commit anything. So we need to make it manually
-    GIT(&[
-        "commit",
-        "--message",
-        &format!("patchy: Merge {local_branch}",),
+    trace!("$ git {}", commit_args.join(" "));
+    get_git_output(
+        spawn_git_with_env(&commit_args, &GIT_ROOT, &dates)?,
+        &commit_args,
+    )?;
+
+    Ok(())
+}
+
+/// Copies the commits after `root` onto a new history which starts with a single commit that has the files of `root`,
+/// like `git checkout --orphan` would. Merge commits are copied without their second parent, and the authors are kept.
+///
+/// Returns each original commit, starting with `root`, with its copy
+pub fn copy_onto_orphan(
+    root: &str,
+    head: &str,
+    root_message: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut parent = GIT(&[
+        "commit-tree",
+        &format!("{root}^{{tree}}"),
+        "-m",
+        root_message,
     ])?;
+    let mut copies = vec![(root.to_owned(), parent.clone())];
 
-    Ok(format!("Merged {remote_branch} successfully"))
+    let commits = GIT(&[
+        "rev-list",
+        "--reverse",
+        "--first-parent",
+        &format!("{root}..{head}"),
+    ])?;
+
+    for commit in commits.lines() {
+        let author = GIT(&["log", "-1", "--format=%an%x00%ae%x00%aI", commit])?;
+        let mut author = author.split('\0');
+        let author: Vec<_> = ["GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL", "GIT_AUTHOR_DATE"]
+            .into_iter()
+            .map(|name| (name, author.next().unwrap_or_default()))
+            .collect();
+        let message = GIT(&["log", "-1", "--format=%B", commit])?;
+        let tree = format!("{commit}^{{tree}}");
+
+        let commit_args = ["commit-tree", &tree, "-p", &parent, "-m", &message];
+        trace!("$ git commit-tree {tree} -p {parent}");
+        parent = get_git_output(
+            spawn_git_with_env(&commit_args, &GIT_ROOT, &author)?,
+            &commit_args,
+        )?;
+
+        copies.push((commit.to_owned(), parent.clone()));
+    }
+
+    Ok(copies)
 }
 
 pub async fn merge_pull_request(
@@ -193,10 +861,12 @@ pub async fn merge_pull_request(
     pull_request: &str,
     pr_title: &str,
     pr_url: &str,
+    options: &MergeOptions<'_>,
 ) -> anyhow::Result<()> {
     merge_into_main(
         &info.branch.local_branch_name,
         &info.branch.upstream_branch_name,
+        options,
     )
     .map_err(|err| {
         let pr = display_link(
@@ -223,7 +893,12 @@ pub async fn merge_pull_request(
             invocations of patchy, see {support_url}\nSkipping this PR. Error \
              message from git:\n{err}",
             &info.branch.local_branch_name.bright_cyan(),
-            "git merge --squash".bright_blue()
+            if options.cherry_pick.is_some() {
+                "git cherry-pick --no-commit"
+            } else {
+                "git merge --squash"
+            }
+            .bright_blue()
         )
     })?;
 
@@ -289,42 +964,429 @@ fn first_available_branch(branch: &str) -> AvailableBranch {
     AvailableBranch::Other(branch_name)
 }
 
-pub async fn fetch_pull_request(
+/// Whether the `repo` is a git repository on the local filesystem instead of on GitHub
+pub fn is_local_repo(repo: &str) -> bool {
+    repo.starts_with("file://") || Path::new(repo).is_absolute()
+}
+
+/// URL of the upstream repository which can be passed to `git fetch`
+pub fn repo_url(repo: &str) -> String {
+    if is_local_repo(repo) {
+        repo.into()
+    } else {
+        format!("https://github.com/{repo}.git")
+    }
+}
+
+/// Name of the temporary remote of the upstream repository. Local repositories are named after their
+/// directory, since paths and `file://` URLs can have characters which are not allowed in remote names
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::git_commands::upstream_remote_alias;
+///
+/// assert!(upstream_remote_alias("helix-editor/helix").ends_with("-helix-editor/helix"));
+/// assert!(upstream_remote_alias("file:///tmp/my fork/helix.git").ends_with("-helix-git"));
+/// assert!(upstream_remote_alias("/tmp/my fork/helix.git").ends_with("-helix-git"));
+/// ```
+pub fn upstream_remote_alias(repo: &str) -> String {
+    if !is_local_repo(repo) {
+        return with_uuid(repo);
+    }
+
+    let path = Path::new(repo.strip_prefix("file://").unwrap_or(repo));
+    let name = path
+        .file_name()
+        .map(|name| normalize_commit_msg(&name.to_string_lossy()))
+        .unwrap_or_else(|| "upstream".into());
+
+    with_uuid(&name)
+}
+
+/// How long the default branch of a repository is cached for, since it can be renamed
+const DEFAULT_BRANCH_CACHE_SECONDS: u64 = 24 * 60 * 60;
+
+/// The default branch of the upstream repository, which is used when the config has no `remote-branch`.
+///
+/// It is cached in the git config of the repository as `<branch> <unix-timestamp>`, so that it is only
+/// requested once a day
+pub async fn request_default_branch(repo: &str, client: &Client) -> anyhow::Result<String> {
+    let cache_key = format!("{APP_NAME}.{repo}.default-branch");
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    if let Ok(cached) = GIT(&["config", "--get", &cache_key]) {
+        match cached.split_once(' ') {
+            Some((default_branch, cached_at))
+                if cached_at.parse::<u64>().is_ok_and(|cached_at| {
+                    now.saturating_sub(cached_at) < DEFAULT_BRANCH_CACHE_SECONDS
+                }) =>
+            {
+                trace!("Using the cached default branch {default_branch} of {repo}");
+                return Ok(default_branch.to_owned());
+            }
+            _ => trace!("The cached default branch of {repo} has expired"),
+        }
+    }
+
+    let default_branch = if is_local_repo(repo) {
+        // e.g. `ref: refs/heads/main	HEAD`
+        GIT(&["ls-remote", "--symref", repo, "HEAD"])?
+            .lines()
+            .find_map(|line| {
+                line.strip_prefix("ref: refs/heads/")?
+                    .strip_suffix("\tHEAD")
+            })
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Could not find the default branch of {repo}"))?
+    } else {
+        let url = format!("https://api.github.com/repos/{repo}");
+
+        make_request::<RepoResponse>(client, &url)
+            .await
+            .map_err(|err| anyhow!("Could not fetch the default branch of {repo}\n{err}\n"))?
+            .default_branch
+    };
+
+    GIT(&["config", &cache_key, &format!("{default_branch} {now}")])?;
+
+    Ok(default_branch)
+}
+
+/// Lists the numbers of all pull requests targeting `base` which are in `state`, oldest first.
+///
+/// For local repositories, every `refs/pull/<number>/head` ref counts as an open pull request,
+/// since there is no way to know which ones were closed
+pub async fn request_pull_request_list(
+    repo: &str,
+    base: &str,
+    state: PullRequestState,
+    client: &Client,
+) -> anyhow::Result<Vec<ListedPullRequest>> {
+    if is_local_repo(repo) {
+        if !state.includes(PullRequestState::Open) {
+            return Ok(vec![]);
+        }
+
+        let refs = GIT(&["ls-remote", repo, "refs/pull/*/head"])?;
+
+        let mut numbers: Vec<u64> = refs
+            .lines()
+            .filter_map(|line| {
+                line.split_once("refs/pull/")?
+                    .1
+                    .strip_suffix("/head")?
+                    .parse()
+                    .ok()
+            })
+            .collect();
+        numbers.sort_unstable();
+
+        return Ok(numbers
+            .iter()
+            .map(|number| ListedPullRequest {
+                number: number.to_string(),
+                state: PullRequestState::Open,
+                title: format!("refs/pull/{number}/head"),
+            })
+            .collect());
+    }
+
+    // the API only knows whether pull requests are open or closed, merged ones are closed too
+    let api_state = match state {
+        PullRequestState::Open => "open",
+        PullRequestState::Closed | PullRequestState::Merged => "closed",
+        PullRequestState::All => "all",
+    };
+
+    let mut pull_requests = vec![];
+
+    // the API returns at most 100 pull requests at a time
+    for page in 1.. {
+        let url = format!(
+            "https://api.github.com/repos/{repo}/pulls?state={api_state}&base={base}&sort=created&direction=asc&per_page=100&page={page}"
+        );
+
+        let page: Vec<PullRequestListItem> = make_request(client, &url)
+            .await
+            .map_err(|err| anyhow!("Could not list the pull requests of {repo}\n{err}\n"))?;
+
+        let is_last_page = page.len() < 100;
+
+        pull_requests.extend(
+            page.iter()
+                .filter(|pull_request| state.includes(pull_request.state()))
+                .map(|pull_request| ListedPullRequest {
+                    number: pull_request.number.to_string(),
+                    state: pull_request.state(),
+                    title: pull_request.title.clone(),
+                }),
+        );
+
+        if is_last_page {
+            break;
+        }
+    }
+
+    Ok(pull_requests)
+}
+
+/// Requests information about a pull request from the GitHub API.
+///
+/// For local repositories, the pull request is expected to be at `refs/pull/<number>/head`,
+/// which is where GitHub stores them so it works for e.g. `git clone --mirror`
+///
+/// This does not touch the repository, so it is safe to run several of these at the same time
+pub async fn request_pull_request(
     repo: &str,
     pull_request: &str,
     client: &Client,
+) -> anyhow::Result<GitHubResponse> {
+    if is_local_repo(repo) {
+        let r#ref = format!("pull/{pull_request}/head");
+        return Ok(GitHubResponse {
+            title: format!("refs/{ref}"),
+            html_url: repo.into(),
+            body: None,
+            merged_at: None,
+            merge_commit_sha: None,
+            commits: 0,
+            head: Head {
+                repo: Repo {
+                    clone_url: repo.into(),
+                },
+                r#ref,
+            },
+        });
+    }
+
+    let url = format!("https://api.github.com/repos/{}/pulls/{pull_request}", repo);
+
+    make_request(client, &url)
+        .await
+        .map_err(|err| anyhow!("Could not fetch pull request #{pull_request}\n{err}\n"))
+}
+
+/// Refuses pull requests with more than `max_commits` commits which are not in the fork yet,
+/// which usually means that they target the wrong base. `0` allows any number of commits
+pub fn check_commit_count(
+    branch: &str,
+    pull_request: &str,
+    max_commits: usize,
+) -> anyhow::Result<()> {
+    if max_commits == 0 {
+        return Ok(());
+    }
+
+    let commits = GIT(&["rev-list", "--count", &format!("HEAD..{branch}")])?
+        .parse::<usize>()
+        .map_err(|err| {
+            anyhow!("Could not count the commits of pull request #{pull_request}\n{err}")
+        })?;
+
+    if commits > max_commits {
+        return Err(anyhow!(
+            "Pull request #{pull_request} has {commits} commits, which is more than `max-pr-commits` allows ({max_commits}).\n  Check that it targets the right base branch, or raise the limit in the config if this is expected"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Moves the fetched branch of a pull request back to `up_to`, so that its later commits are not merged
+pub fn leave_out_commits_after(
+    branch: &str,
+    pull_request: &str,
+    up_to: &str,
+) -> anyhow::Result<()> {
+    let commit = GIT(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{up_to}^{{commit}}"),
+    ])
+    .map_err(|_| anyhow!("Could not find commit {up_to} of pull request #{pull_request}"))?;
+
+    if GIT(&["merge-base", "--is-ancestor", &commit, branch]).is_err() {
+        return Err(anyhow!(
+            "Commit {up_to} is not one of the commits of pull request #{pull_request}"
+        ));
+    }
+
+    // the commits before the pull request are already in the fork
+    if GIT(&["merge-base", "--is-ancestor", &commit, "HEAD"]).is_ok() {
+        return Err(anyhow!(
+            "Commit {up_to} is from before pull request #{pull_request}, not one of its commits"
+        ));
+    }
+
+    GIT(&["branch", "--force", branch, &commit])?;
+
+    trace!("Left out the commits of pull request #{pull_request} after {up_to}");
+
+    Ok(())
+}
+
+/// Moves the fetched branch of a pull request to the commit which merged it upstream, for `use-merge-commit`.
+/// Returns how that commit is applied, depending on whether the pull request was merged, squashed or rebased
+///
+/// # Examples
+///
+/// ```rust
+/// # use patchy::test_repo::{commit, git, temp_repo};
+/// # let repo = temp_repo();
+/// # commit("base");
+/// # git(&["switch", "--quiet", "--create", "pull-request"]);
+/// # commit("one");
+/// # commit("two");
+/// # git(&["switch", "--quiet", "main"]);
+/// # commit("upstream");
+/// # for branch in ["merged", "squashed", "rebased"] {
+/// #     git(&["branch", branch]);
+/// #     git(&["branch", &format!("{branch}-pull-request"), "pull-request"]);
+/// # }
+/// # git(&["switch", "--quiet", "merged"]);
+/// # git(&["merge", "--quiet", "--no-ff", "--message=merge", "pull-request"]);
+/// # git(&["switch", "--quiet", "squashed"]);
+/// # git(&["merge", "--quiet", "--squash", "pull-request"]);
+/// # git(&["commit", "--quiet", "--message=squashed"]);
+/// # git(&["switch", "--quiet", "rebased"]);
+/// # git(&["cherry-pick", "main..pull-request"]);
+/// # git(&["switch", "--quiet", "main"]);
+/// use patchy::{git_commands::fetch_merge_commit, types::CherryPick};
+///
+/// let upstream = repo.path().to_str().unwrap();
+/// // the pull request has two commits
+/// let cherry_pick = |merged: &str| {
+///     let merge_commit = git(&["rev-parse", merged]);
+///     fetch_merge_commit(&format!("{merged}-pull-request"), "1", &merge_commit, 2, upstream).unwrap()
+/// };
+///
+/// assert_eq!(cherry_pick("merged"), CherryPick::Merge);
+/// assert_eq!(cherry_pick("squashed"), CherryPick::Commit);
+/// assert_eq!(cherry_pick("rebased"), CherryPick::Rebased { commits: 2 });
+/// ```
+pub fn fetch_merge_commit(
+    branch: &str,
+    pull_request: &str,
+    merge_commit: &str,
+    commits: u64,
+    repo: &str,
+) -> anyhow::Result<CherryPick> {
+    GIT(&["fetch", "--no-tags", &repo_url(repo), merge_commit]).map_err(|err| {
+        anyhow!(
+            "Could not fetch commit {merge_commit} which merged pull request #{pull_request}, it may not exist in {repo}\n{err}"
+        )
+    })?;
+
+    // e.g. `<commit> <first parent> <second parent>`
+    let parents = GIT(&["rev-list", "--parents", "--max-count=1", merge_commit])?
+        .split_whitespace()
+        .count()
+        - 1;
+
+    let cherry_pick = if parents > 1 {
+        CherryPick::Merge
+    } else if commits <= 1 {
+        CherryPick::Commit
+    } else {
+        // Rebasing leaves the commit at the end of the pull request with the same changes, marked `-`,
+        // or the commits themselves if they were on top of upstream already. A squashed commit has the
+        // changes of all of them, so it is only the same with one commit.
+        let is_rebased = GIT(&["cherry", merge_commit, branch])?
+            .lines()
+            .last()
+            .is_none_or(|head| head.starts_with('-'));
+
+        if !is_rebased {
+            CherryPick::Commit
+        } else if GIT(&[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{merge_commit}~{commits}"),
+        ])
+        .is_ok()
+        {
+            CherryPick::Rebased { commits }
+        } else {
+            return Err(anyhow!(
+                "Pull request #{pull_request} was rebased onto upstream, but the {commits} commits before {merge_commit} could not be found"
+            ));
+        }
+    };
+
+    GIT(&["branch", "--force", branch, merge_commit])?;
+
+    trace!(
+        "Using commit {merge_commit} which merged pull request #{pull_request}, as {cherry_pick:?}"
+    );
+
+    Ok(cherry_pick)
+}
+
+/// Fetches the branch of a pull request, whose information was obtained with `request_pull_request`
+///
+/// `repo` is the upstream repository, which is used when fetching from `refs/pull/<number>/head`
+pub fn fetch_pull_request(
+    response: GitHubResponse,
+    pull_request: &str,
     custom_branch_name: Option<&str>,
     commit_hash: &Option<String>,
+    repo: &str,
+    fetch_from: FetchFrom,
+    depth: Option<u32>,
 ) -> anyhow::Result<(GitHubResponse, BranchAndRemote)> {
-    let url = format!("https://api.github.com/repos/{}/pulls/{pull_request}", repo);
+    let local_branch_name = custom_branch_name.map(|s| s.into()).unwrap_or({
+        let branch_name = &format!("{pull_request}/{}", &response.head.r#ref);
 
-    let response = make_request(client, &url)
-        .await
-        .map_err(|err| anyhow!("Could not fetch pull request #{pull_request}\n{err}\n"))?;
+        match first_available_branch(branch_name) {
+            AvailableBranch::First => branch_name.to_string(),
+            AvailableBranch::Other(branch) => branch,
+        }
+    });
+    let local_remote_alias = with_uuid(&format!(
+        "{title}-{}",
+        pull_request,
+        title = normalize_commit_msg(&response.html_url)
+    ));
+
+    if fetch_from == FetchFrom::PullRequestRef {
+        let info = BranchAndRemote {
+            branch: Branch {
+                upstream_branch_name: format!("refs/pull/{pull_request}/head"),
+                local_branch_name: local_branch_name.clone(),
+            },
+            remote: Remote {
+                repository_url: repo_url(repo),
+                local_remote_alias: local_remote_alias.clone(),
+            },
+        };
+
+        match add_remote_branch(&info, commit_hash, depth) {
+            Ok(()) => return Ok((response, info)),
+            Err(err) => trace!(
+                "Could not fetch refs/pull/{pull_request}/head, fetching branch {} instead\n{err}",
+                response.head.r#ref
+            ),
+        }
+    }
 
     let info = BranchAndRemote {
         branch: Branch {
             upstream_branch_name: response.head.r#ref.clone(),
-            local_branch_name: custom_branch_name.map(|s| s.into()).unwrap_or({
-                let branch_name = &format!("{pull_request}/{}", &response.head.r#ref);
-
-                match first_available_branch(branch_name) {
-                    AvailableBranch::First => branch_name.to_string(),
-                    AvailableBranch::Other(branch) => branch,
-                }
-            }),
+            local_branch_name,
         },
         remote: Remote {
             repository_url: response.head.repo.clone_url.clone(),
-            local_remote_alias: with_uuid(&format!(
-                "{title}-{}",
-                pull_request,
-                title = normalize_commit_msg(&response.html_url)
-            )),
+            local_remote_alias,
         },
     };
 
-    add_remote_branch(&info, commit_hash).map_err(|err| {
+    add_remote_branch(&info, commit_hash, depth).map_err(|err| {
         anyhow!("Could not add remote branch for pull request #{pull_request}, skipping.\n{err}")
     })?;
 
@@ -1,11 +1,22 @@
 pub mod backup;
 pub mod commands;
+pub mod events;
 pub mod flags;
 pub mod git_commands;
+pub mod lock;
+pub mod profile;
+pub mod redact;
+pub mod resume;
+pub mod retry;
+pub mod run_log;
+pub mod step_summary;
+#[doc(hidden)]
+pub mod test_repo;
 pub mod types;
 pub mod utils;
 
 pub static CONFIG_ROOT: &str = ".patchy";
 pub static CONFIG_FILE: &str = "config.toml";
+pub static LOG_FILE: &str = "log.jsonl";
 pub static APP_NAME: &str = "patchy";
 pub static INDENT: &str = "  ";
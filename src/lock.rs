@@ -0,0 +1,97 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use colored::Colorize;
+
+use crate::{
+    commands::run::RUN_CONCURRENCY_GROUP_FLAG,
+    git_commands::{GIT, GIT_ROOT},
+    info, trace,
+    types::CommandArgs,
+    APP_NAME,
+};
+
+/// Held by a run of a concurrency group while it changes the repository. Released when dropped
+pub struct GroupLock {
+    path: PathBuf,
+}
+
+impl GroupLock {
+    /// Waits until no other run of the concurrency group passed with `--concurrency-group` holds the lock.
+    ///
+    /// Returns `None` if the flag was not passed
+    pub async fn acquire_from_args(args: &CommandArgs) -> anyhow::Result<Option<Self>> {
+        let Some(group) = args
+            .iter()
+            .find_map(|arg| RUN_CONCURRENCY_GROUP_FLAG.extract_from_arg(arg))
+        else {
+            return Ok(None);
+        };
+
+        if group.is_empty()
+            || !group
+                .chars()
+                .all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_')
+        {
+            return Err(anyhow!(
+                "Invalid flag {}{group}, the name of a concurrency group can only contain letters, numbers, dashes and underscores",
+                RUN_CONCURRENCY_GROUP_FLAG.long
+            ));
+        }
+
+        Self::acquire(&group).await.map(Some)
+    }
+
+    async fn acquire(group: &str) -> anyhow::Result<Self> {
+        // worktrees of the same repository share this directory, and all of the fetched objects
+        let common_dir = GIT_ROOT.join(GIT(&["rev-parse", "--git-common-dir"])?);
+        let group_dir = common_dir.join(APP_NAME).join("groups");
+        fs::create_dir_all(&group_dir)?;
+
+        let path = group_dir.join(format!("{group}.lock"));
+        let mut waiting = false;
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    trace!("Acquired lock {path:?} of concurrency group {group}");
+
+                    return Ok(Self { path });
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if !waiting {
+                        let holder = fs::read_to_string(&path).unwrap_or_default();
+                        info!(
+                            "Waiting for another run of concurrency group {} (process {}) to finish. If there is none, remove {}",
+                            group.bright_cyan(),
+                            holder.trim(),
+                            path.to_string_lossy()
+                        );
+                        waiting = true;
+                    }
+
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+                Err(err) => {
+                    return Err(anyhow!(
+                        "Could not create lock {path:?} for concurrency group {group}\n{err}"
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for GroupLock {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            trace!("Could not remove lock {:?}: {err}", self.path);
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use colored::Colorize;
-use patchy::commands::help::{HELP_FLAG, VERSION_FLAG};
-use patchy::commands::{gen_patch, help, init, pr_fetch, run};
+use patchy::commands::help::{HELP_FLAG, SELFTEST_FLAG, VERSION_FLAG};
+use patchy::commands::run::RUN_WATCH_FLAG;
+use patchy::commands::{
+    export, freeze, gen_patch, help, init, list, log, pr_fetch, run, run_all, select, selftest,
+    test_patches, watch, which,
+};
 use patchy::fail;
+use patchy::git_commands::{find_git, GIT_NOT_FOUND_EXIT_CODE};
 use std::env;
 
 use patchy::types::CommandArgs;
@@ -12,8 +17,17 @@ async fn process_subcommand(subcommand: &str, args: CommandArgs) -> Result<()> {
     match subcommand {
         // main commands
         "init" => init(&args)?,
+        "run" if RUN_WATCH_FLAG.is_in_args(&args) => watch(&args).await?,
         "run" => run(&args).await?,
+        "run-all" => run_all(&args).await?,
         "gen-patch" => gen_patch(&args)?,
+        "log" => log(&args)?,
+        "list" => list(&args).await?,
+        "select" => select(&args).await?,
+        "which" => which(&args)?,
+        "freeze" => freeze(&args).await?,
+        "export" => export(&args)?,
+        "test-patches" => test_patches(&args).await?,
         // lower level commands
         "pr-fetch" => pr_fetch(&args).await?,
         unrecognized => {
@@ -53,12 +67,23 @@ async fn main() -> Result<()> {
         args.insert(subcommand.clone());
     }
 
+    // nothing works without git, so there is no point in continuing
+    if let Some(message) = find_git() {
+        fail!("{message}");
+        std::process::exit(GIT_NOT_FOUND_EXIT_CODE);
+    }
+
     if HELP_FLAG.is_in_args(&args) {
         help(Some(&subcommand))
     } else if VERSION_FLAG.is_in_args(&args) {
         print!("{}", env!("CARGO_PKG_VERSION"));
 
         Ok(())
+    } else if SELFTEST_FLAG.is_in_args(&args) {
+        selftest().inspect_err(|err| {
+            fail!("{err}");
+            std::process::exit(1);
+        })
     } else {
         match process_subcommand(subcommand.as_str(), args).await {
             Ok(()) => Ok(()),
@@ -0,0 +1,115 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use colored::Colorize;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    events::{emit, Event},
+    flags::{EMIT_EVENTS, PROFILE},
+    INDENT,
+};
+
+/// How long a part of the run took, for `--profile`
+#[derive(Serialize, Debug, Clone)]
+pub struct Phase {
+    pub name: String,
+    /// How many phases this one is inside of
+    pub depth: usize,
+    pub milliseconds: u128,
+}
+
+/// Phases in the order they started. The duration is filled in once the phase ends
+static PHASES: Lazy<Mutex<Vec<Phase>>> = Lazy::new(Mutex::default);
+
+/// How many phases are running at the moment
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Records how long the phase took once it's dropped
+pub struct Timer(Option<(usize, Instant)>);
+
+/// Starts timing a phase of the run. Without `--profile` this does nothing
+pub fn start(name: &str) -> Timer {
+    if !*PROFILE {
+        return Timer(None);
+    }
+
+    let depth = DEPTH.fetch_add(1, Ordering::Relaxed);
+    let mut phases = PHASES.lock().unwrap();
+    phases.push(Phase {
+        name: name.to_owned(),
+        depth,
+        milliseconds: 0,
+    });
+
+    Timer(Some((phases.len() - 1, Instant::now())))
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let Some((index, started)) = self.0 else {
+            return;
+        };
+
+        DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(phase) = PHASES.lock().unwrap().get_mut(index) {
+            phase.milliseconds = started.elapsed().as_millis();
+        }
+    }
+}
+
+/// Shows how long each phase took once it's dropped, which is at the end of the run even if it fails
+pub struct Report(());
+
+/// Starts the report of a run. `run --watch` runs many times, and each report only has the phases of its own run
+pub fn report() -> Report {
+    PHASES.lock().unwrap().clear();
+    DEPTH.store(0, Ordering::Relaxed);
+
+    Report(())
+}
+
+impl Drop for Report {
+    fn drop(&mut self) {
+        let phases = std::mem::take(&mut *PHASES.lock().unwrap());
+
+        if !*PROFILE {
+            return;
+        }
+
+        if *EMIT_EVENTS {
+            emit(Event::Profile { phases: &phases });
+            return;
+        }
+
+        let total = phases
+            .iter()
+            .filter(|phase| phase.depth == 0)
+            .map(|phase| phase.milliseconds)
+            .sum::<u128>()
+            .max(1);
+        let width = phases
+            .iter()
+            .map(|phase| phase.name.len() + phase.depth * INDENT.len())
+            .max()
+            .unwrap_or_default();
+
+        eprintln!("\n{INDENT}{}", "Profile:".bold());
+
+        for phase in &phases {
+            let name = format!("{}{}", INDENT.repeat(phase.depth), phase.name);
+            eprintln!(
+                "{INDENT}{INDENT}{name:<width$}  {}  {}",
+                format!("{:>8}", format!("{}ms", phase.milliseconds)).bright_blue(),
+                format!("{:>3}%", phase.milliseconds * 100 / total).bright_black()
+            );
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    git_commands::{GIT, GIT_ROOT},
+    run_log::MergedPullRequest,
+    step_summary::Outcome,
+    types::QueuedPullRequest,
+    APP_NAME,
+};
+
+/// Written when a run stops at a conflict because of `on-conflict = "leave"`, so that
+/// `patchy run --continue` can pick up where it stopped
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContinueState {
+    /// What to check out once the run is done
+    pub previous_branch: String,
+    /// Remote and branch of upstream, which are removed once the run is done
+    pub upstream_remote: String,
+    pub upstream_branch: String,
+    /// The commit the run started from
+    pub run_start: String,
+    pub has_stashed_config: bool,
+    pub conflict: ConflictedPullRequest,
+    pub merged_pull_requests: Vec<MergedPullRequest>,
+    pub failed_pull_requests: Vec<String>,
+    pub outcomes: Vec<(String, Outcome)>,
+    /// Pull requests after the conflicting one
+    pub remaining_pull_requests: Vec<QueuedPullRequest>,
+    /// Names and contents of the config files, which are not in the working tree of upstream
+    pub backups: Option<Vec<(String, String)>>,
+}
+
+/// The pull request whose conflict is resolved by hand
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConflictedPullRequest {
+    pub number: String,
+    pub branch: String,
+    pub remote: String,
+    pub trailers: Vec<String>,
+    pub preserve_dates: bool,
+    #[serde(default)]
+    pub signoff: bool,
+}
+
+impl ContinueState {
+    /// Each worktree can have its own stopped run, so this is not in the common git directory
+    pub fn path() -> anyhow::Result<PathBuf> {
+        let git_dir = GIT_ROOT.join(GIT(&["rev-parse", "--git-dir"])?);
+        Ok(git_dir.join(APP_NAME).join("continue.json"))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path()?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .map_err(|err| anyhow!("Could not save the state of the run to {path:?}\n{err}"))
+    }
+
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let path = Self::path()?;
+
+        let Ok(contents) = fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+
+        serde_json::from_str(&contents).map(Some).map_err(|err| {
+            anyhow!("Could not read the state of the stopped run at {path:?}\n{err}")
+        })
+    }
+
+    /// Once the run continues, it can't be continued again
+    pub fn remove() -> anyhow::Result<()> {
+        fs::remove_file(Self::path()?)?;
+        Ok(())
+    }
+}
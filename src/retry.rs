@@ -0,0 +1,124 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use rand::Rng;
+
+use crate::{
+    commands::run::{
+        RUN_DEADLINE_FLAG, RUN_FETCH_DEADLINE_FLAG, RUN_MAX_FETCH_RETRIES_PER_PR_FLAG,
+    },
+    flags::Flag,
+    trace,
+    types::CommandArgs,
+};
+
+/// How failed network operations get retried.
+///
+/// Each pull request gets its own number of retries, so a single flaky one can't use up the budget of the others.
+/// Once the deadline passes, nothing is retried anymore
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub deadline: Option<Instant>,
+}
+
+fn parse_seconds_flag(args: &CommandArgs, flag: &Flag) -> anyhow::Result<Option<u64>> {
+    args.iter()
+        .find_map(|arg| flag.extract_from_arg(arg))
+        .map(|value| {
+            value.parse::<u64>().map_err(|_| {
+                anyhow!(
+                    "Invalid flag {}{value}, expected a non-negative number of seconds",
+                    flag.long
+                )
+            })
+        })
+        .transpose()
+}
+
+fn parse_count_flag(args: &CommandArgs, flag: &Flag) -> anyhow::Result<Option<usize>> {
+    args.iter()
+        .find_map(|arg| flag.extract_from_arg(arg))
+        .map(|value| {
+            value.parse::<usize>().map_err(|_| {
+                anyhow!(
+                    "Invalid flag {}{value}, expected a non-negative number of retries",
+                    flag.long
+                )
+            })
+        })
+        .transpose()
+}
+
+/// When the whole run has to be done by, if there is a limit
+pub fn run_deadline_from_args(args: &CommandArgs) -> anyhow::Result<Option<Instant>> {
+    Ok(parse_seconds_flag(args, &RUN_DEADLINE_FLAG)?
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds)))
+}
+
+impl RetryPolicy {
+    pub fn from_args(args: &CommandArgs) -> anyhow::Result<Self> {
+        let fetch_deadline = parse_seconds_flag(args, &RUN_FETCH_DEADLINE_FLAG)?
+            .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+
+        Ok(Self {
+            // a pull request which doesn't exist or can't be merged won't start working by itself
+            max_retries: parse_count_flag(args, &RUN_MAX_FETCH_RETRIES_PER_PR_FLAG)?.unwrap_or(0),
+            // there's no point in retrying if the run is going to stop anyway
+            deadline: [fetch_deadline, run_deadline_from_args(args)?]
+                .into_iter()
+                .flatten()
+                .min(),
+        })
+    }
+
+    fn can_retry(&self, retries: usize) -> bool {
+        retries < self.max_retries
+            && self
+                .deadline
+                .is_none_or(|deadline| Instant::now() < deadline)
+    }
+
+    /// Runs the operation until it succeeds or runs out of retries.
+    ///
+    /// `previous_retries` is how many retries were already used up for the same pull request.
+    /// Returns the last result, and how many times the pull request has been retried in total
+    pub async fn retry<T, F, Fut>(
+        &self,
+        previous_retries: usize,
+        mut operation: F,
+    ) -> (anyhow::Result<T>, usize)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut retries = previous_retries;
+
+        loop {
+            match operation().await {
+                Err(err) if self.can_retry(retries) => {
+                    retries += 1;
+                    // many runs failing at the same time, e.g. in CI, should not all retry at the same time
+                    let delay = Duration::from_secs(retries as u64)
+                        .mul_f64(rand::thread_rng().gen_range(0.5..1.5));
+
+                    if self
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() + delay >= deadline)
+                    {
+                        return (Err(err), retries - 1);
+                    }
+
+                    trace!(
+                        "Retrying in {delay:?} ({retries}/{}): {err}",
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return (result, retries),
+            }
+        }
+    }
+}
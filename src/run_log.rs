@@ -0,0 +1,67 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{git_commands::CONFIG_LOCATION, CONFIG_ROOT, LOG_FILE};
+
+/// A single invocation of `patchy run`, recorded in the log
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunLogEntry {
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+    /// Hash of the configuration file's contents, as given by `git hash-object`
+    pub config_hash: String,
+    /// The commit which `local-branch` pointed to after the run
+    pub sha: String,
+    /// The commit after merging the pull requests, which the patches were applied on top of
+    #[serde(default)]
+    pub base: Option<String>,
+    pub pull_requests: Vec<MergedPullRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MergedPullRequest {
+    pub number: String,
+    /// Local branch which patchy created for the pull request
+    pub branch: String,
+    /// Commit which merged the pull request
+    pub commit: String,
+}
+
+/// The log is never committed, so unlike the config files it stays in the working tree when switching branches
+pub fn log_path() -> PathBuf {
+    CONFIG_LOCATION.root.join(LOG_FILE)
+}
+
+pub fn append_run_log(entry: &RunLogEntry) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())?;
+
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+    Ok(())
+}
+
+/// Reads every run from the log, oldest first
+pub fn read_run_log() -> anyhow::Result<Vec<RunLogEntry>> {
+    let Ok(contents) = fs::read_to_string(log_path()) else {
+        return Ok(vec![]);
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).map_err(|err| {
+                anyhow!("Could not parse entry of {CONFIG_ROOT}/{LOG_FILE}: {line}\n{err}")
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, env, fs::OpenOptions, io::Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{utils::strip_ansi, APP_NAME};
+
+/// What happened to a pull request or a patch during a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Merged,
+    /// With the error from git
+    Conflict(String),
+    /// With the error from fetching
+    FetchFailed(String),
+    Applied,
+    /// With the error from applying the patch
+    Failed(String),
+    Skipped(String),
+}
+
+/// Report of a run, written as Markdown to `$GITHUB_STEP_SUMMARY` so it shows up in the GitHub Actions UI
+#[derive(Debug, Default)]
+pub struct StepSummary {
+    pub pull_requests: Vec<(String, Outcome)>,
+    pub patches: Vec<(String, Outcome)>,
+}
+
+impl StepSummary {
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use patchy::step_summary::{Outcome, StepSummary};
+    ///
+    /// let summary = StepSummary {
+    ///     pull_requests: vec![
+    ///         ("1".into(), Outcome::Merged),
+    ///         ("2".into(), Outcome::FetchFailed("not found".into())),
+    ///     ],
+    ///     patches: vec![("fix".into(), Outcome::Skipped("tag v2 does not exist".into()))],
+    /// };
+    ///
+    /// let markdown = summary.to_markdown(&BTreeMap::from([("2".into(), 2)]));
+    ///
+    /// assert!(markdown.contains("| #1 | ✅ Merged |"));
+    /// assert!(markdown.contains("| #2 | ❌ Could not fetch, retried 2 times |"));
+    /// assert!(markdown.contains("| fix | ⏭️ Skipped: tag v2 does not exist |"));
+    /// ```
+    pub fn to_markdown(&self, retries: &BTreeMap<String, usize>) -> String {
+        let mut markdown = format!("## {APP_NAME} run\n\n");
+
+        let describe = |name: &str, outcome: &Outcome| match outcome {
+            Outcome::Merged => "✅ Merged".to_owned(),
+            Outcome::Applied => "✅ Applied".to_owned(),
+            Outcome::Conflict(_) => "❌ Merge conflict".to_owned(),
+            Outcome::Failed(_) => "❌ Could not apply".to_owned(),
+            Outcome::FetchFailed(_) => match retries.get(name) {
+                Some(retries) => format!(
+                    "❌ Could not fetch, retried {retries} time{}",
+                    if *retries == 1 { "" } else { "s" }
+                ),
+                None => "❌ Could not fetch".to_owned(),
+            },
+            // the reason is not controlled by us, so it must not break the table
+            Outcome::Skipped(reason) => format!("⏭️ Skipped: {}", reason.replace('|', "\\|")),
+        };
+
+        if self.pull_requests.is_empty() {
+            markdown.push_str("No pull requests were merged.\n");
+        } else {
+            markdown.push_str("| Pull request | Result |\n| --- | --- |\n");
+            for (pull_request, outcome) in &self.pull_requests {
+                markdown.push_str(&format!(
+                    "| #{pull_request} | {} |\n",
+                    describe(pull_request, outcome)
+                ));
+            }
+        }
+
+        if !self.patches.is_empty() {
+            markdown.push_str("\n| Patch | Result |\n| --- | --- |\n");
+            for (patch, outcome) in &self.patches {
+                markdown.push_str(&format!("| {patch} | {} |\n", describe(patch, outcome)));
+            }
+        }
+
+        markdown
+    }
+
+    /// A JUnit XML report, with a test case for each pull request and patch, which CI systems can show like test results
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    /// use patchy::step_summary::{Outcome, StepSummary};
+    ///
+    /// let summary = StepSummary {
+    ///     pull_requests: vec![
+    ///         ("1".into(), Outcome::Merged),
+    ///         ("2".into(), Outcome::Conflict("CONFLICT (content): Merge conflict in <main.rs>".into())),
+    ///     ],
+    ///     patches: vec![
+    ///         ("fix".into(), Outcome::Skipped("tag v2 does not exist".into())),
+    ///         ("typo".into(), Outcome::Failed("Patch format detection failed.".into())),
+    ///     ],
+    /// };
+    ///
+    /// let junit = summary.to_junit(&BTreeMap::new());
+    ///
+    /// assert!(junit.contains(r#"<testsuite name="patches" tests="2" failures="1" skipped="1">"#));
+    /// assert!(junit.contains(r#"<failure message="Could not apply">Patch format detection failed.</failure>"#));
+    /// assert!(junit.contains(r#"<testsuite name="pull requests" tests="2" failures="1" skipped="0">"#));
+    /// assert!(junit.contains(r##"<testcase classname="patchy.pull-requests" name="#1"/>"##));
+    /// assert!(junit.contains(r#"<failure message="Merge conflict">CONFLICT (content): Merge conflict in &lt;main.rs&gt;</failure>"#));
+    /// assert!(junit.contains(r#"<skipped message="tag v2 does not exist"/>"#));
+    /// ```
+    pub fn to_junit(&self, retries: &BTreeMap<String, usize>) -> String {
+        let suites = [
+            ("pull requests", "pull-requests", "#", &self.pull_requests),
+            ("patches", "patches", "", &self.patches),
+        ];
+
+        let count = |outcomes: &[(String, Outcome)], predicate: fn(&Outcome) -> bool| {
+            outcomes
+                .iter()
+                .filter(|(_, outcome)| predicate(outcome))
+                .count()
+        };
+        let is_failure = |outcome: &Outcome| {
+            matches!(
+                outcome,
+                Outcome::Conflict(_) | Outcome::FetchFailed(_) | Outcome::Failed(_)
+            )
+        };
+        let is_skipped = |outcome: &Outcome| matches!(outcome, Outcome::Skipped(_));
+
+        let all: Vec<_> = self
+            .pull_requests
+            .iter()
+            .chain(&self.patches)
+            .cloned()
+            .collect();
+        let mut junit = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"{APP_NAME}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+            all.len(),
+            count(&all, is_failure),
+            count(&all, is_skipped)
+        );
+
+        for (suite, class, prefix, outcomes) in suites {
+            junit.push_str(&format!(
+                "  <testsuite name=\"{suite}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+                outcomes.len(),
+                count(outcomes, is_failure),
+                count(outcomes, is_skipped)
+            ));
+
+            for (name, outcome) in outcomes {
+                let testcase = format!(
+                    "<testcase classname=\"{APP_NAME}.{class}\" name=\"{}\"",
+                    escape_xml(&format!("{prefix}{name}"))
+                );
+                let failure = |message: String, error: &str| {
+                    format!(
+                        "    {testcase}>\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                        escape_xml(&message),
+                        escape_xml(error)
+                    )
+                };
+
+                junit.push_str(&match outcome {
+                    Outcome::Merged | Outcome::Applied => format!("    {testcase}/>\n"),
+                    Outcome::Skipped(reason) => format!(
+                        "    {testcase}>\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                        escape_xml(reason)
+                    ),
+                    Outcome::Conflict(error) => failure("Merge conflict".to_owned(), error),
+                    Outcome::Failed(error) => failure("Could not apply".to_owned(), error),
+                    Outcome::FetchFailed(error) => failure(
+                        match retries.get(name) {
+                            Some(retries) => format!(
+                                "Could not fetch, retried {retries} time{}",
+                                if *retries == 1 { "" } else { "s" }
+                            ),
+                            None => "Could not fetch".to_owned(),
+                        },
+                        error,
+                    ),
+                });
+            }
+
+            junit.push_str("  </testsuite>\n");
+        }
+
+        junit.push_str("</testsuites>\n");
+
+        junit
+    }
+
+    /// Appends the summary to the file in `$GITHUB_STEP_SUMMARY`.
+    ///
+    /// Returns `false` if that variable is not set, which means we are not running in GitHub Actions
+    pub fn write(&self, retries: &BTreeMap<String, usize>) -> anyhow::Result<bool> {
+        let Some(path) = env::var_os("GITHUB_STEP_SUMMARY").filter(|path| !path.is_empty()) else {
+            return Ok(false);
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        writeln!(file, "{}", self.to_markdown(retries))?;
+
+        Ok(true)
+    }
+}
+
+/// Escapes text for XML, without the colors and links of the terminal which XML can't contain
+fn escape_xml(text: &str) -> String {
+    strip_ansi(text)
+        .chars()
+        .filter(|ch| !ch.is_control() || matches!(ch, '\n' | '\t'))
+        .fold(String::with_capacity(text.len()), |mut escaped, ch| {
+            match ch {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                ch => escaped.push(ch),
+            }
+            escaped
+        })
+}
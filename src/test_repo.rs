@@ -0,0 +1,56 @@
+//! Repositories for the examples in the docs, which need real commits and branches to run git commands against
+
+use std::{env, fs, process::Command};
+
+use tempfile::TempDir;
+
+/// Creates a repository in a temporary directory and makes it the current directory.
+/// Its default branch is `main`, and commits are made by the same author every time.
+///
+/// The directory is removed once the returned value is dropped
+pub fn temp_repo() -> TempDir {
+    for (name, value) in [
+        ("GIT_AUTHOR_NAME", "a"),
+        ("GIT_AUTHOR_EMAIL", "a@b"),
+        ("GIT_COMMITTER_NAME", "a"),
+        ("GIT_COMMITTER_EMAIL", "a@b"),
+    ] {
+        env::set_var(name, value);
+    }
+
+    let repo = tempfile::tempdir().unwrap();
+    env::set_current_dir(repo.path()).unwrap();
+    git(&["init", "--quiet", "--initial-branch=main"]);
+
+    repo
+}
+
+/// Runs git in the current directory and returns its output, panicking if it fails
+pub fn git(args: &[&str]) -> String {
+    git_with_env(args, &[])
+}
+
+/// Like `git`, with extra environment variables such as `GIT_COMMITTER_DATE`
+pub fn git_with_env(args: &[&str], env: &[(&str, &str)]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "git {} failed\n{}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap().trim().to_owned()
+}
+
+/// Commits a file named `file` which contains its own name, on the current branch
+pub fn commit(file: &str) {
+    fs::write(file, file).unwrap();
+    git(&["add", file]);
+    git(&["commit", "--quiet", &format!("--message={file}")]);
+}
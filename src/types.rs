@@ -1,34 +1,399 @@
-use std::collections::HashSet;
+use std::{collections::BTreeMap, marker::PhantomData};
 
 use indexmap::IndexSet;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{
+    commands::{pr_fetch::ignore_octothorpe, run::parse_if_maybe_hash},
+    run_log::MergedPullRequest,
+};
 
 pub type CommandArgs = IndexSet<String>;
 
 #[derive(Deserialize, Debug)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Configuration {
     pub local_branch: String,
-    pub patches: Option<HashSet<String>>,
-    pub pull_requests: Vec<String>,
-    pub remote_branch: String,
+    pub patches: Option<Vec<Patch>>,
+    pub pull_requests: Vec<PullRequest>,
+    /// Defaults to the default branch of `repo`
+    pub remote_branch: Option<String>,
     pub repo: String,
+    /// Trailers added to the commit of each merged pull request
+    #[serde(default)]
+    pub commit_trailers: Vec<String>,
+    /// Extra options for `git format-patch` used by `patchy gen-patch`
+    #[serde(default)]
+    pub diff_options: Vec<String>,
+    /// Environment variables for hooks and git commands during the run
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Keep the dates of the original commits of pull requests and patches
+    #[serde(default)]
+    pub preserve_dates: bool,
+    /// Add a `Signed-off-by` trailer to the commits of patches
+    #[serde(default = "default_am_signoff")]
+    pub am_signoff: bool,
+    /// Add a `Signed-off-by` trailer to the commits of merged pull requests
+    #[serde(default)]
+    pub merge_signoff: bool,
+    /// Keep the branch which `local-branch` is copied from, instead of renaming it to `local-branch`
+    #[serde(default)]
+    pub keep_temp_branch: bool,
+    /// Make `local-branch` a new history without the commits of upstream, which starts with a snapshot of its files
+    #[serde(default)]
+    pub orphan: bool,
+    /// Stop the run when a pull request conflicts, instead of skipping it
+    #[serde(default)]
+    pub strict: bool,
+    /// Pull requests with more commits than this are not merged, `0` means no limit
+    #[serde(default = "default_max_pr_commits")]
+    pub max_pr_commits: usize,
+    /// What to do when a pull request conflicts
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    /// Prevents `patchy run` from executing unless explicitly overridden
+    #[serde(default)]
+    pub readonly: bool,
+    #[serde(default)]
+    pub fetch_from: FetchFrom,
+    /// Command which is run on the files changed by the run, which are passed as arguments
+    #[serde(default)]
+    pub format_changed: Vec<String>,
+    /// Git maintenance commands to run against the repository before fetching, e.g. `["gc", "--auto"]`
+    #[serde(default)]
+    pub pre_fetch_git: Vec<Vec<String>>,
+    /// Also merge every pull request targeting `remote-branch` which is in the given state
+    pub discover_pull_requests: Option<DiscoverPullRequests>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn default_max_pr_commits() -> usize {
+    250
+}
+
+fn default_am_signoff() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DiscoverPullRequests {
+    #[serde(default)]
+    pub state: PullRequestState,
+    /// Discovered pull requests which should not be merged
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullRequestState {
+    #[default]
+    Open,
+    /// Closed without being merged
+    Closed,
+    Merged,
+    All,
+}
+
+impl PullRequestState {
+    /// Whether a pull request in this state is included by the filter
+    pub fn includes(self, state: PullRequestState) -> bool {
+        self == PullRequestState::All || self == state
+    }
+}
+
+impl std::fmt::Display for PullRequestState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PullRequestState::Open => "open",
+            PullRequestState::Closed => "closed",
+            PullRequestState::Merged => "merged",
+            PullRequestState::All => "all",
+        })
+    }
+}
+
+/// What happens when a pull request can't be merged because of a conflict
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Undo the merge and go on with the next pull request
+    #[default]
+    Skip,
+    /// Stop the run with the conflict markers in the working tree, so it can be resolved by hand
+    /// and the run continued with `--continue`
+    Leave,
+}
+
+/// Where the commits of a pull request are fetched from
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FetchFrom {
+    /// The branch of the pull request, in the repository of its author
+    #[default]
+    Branch,
+    /// The `refs/pull/<number>/head` ref of the upstream repository, which exists even if the branch was deleted.
+    /// Falls back to the branch if the ref cannot be fetched
+    PullRequestRef,
+}
+
+/// Either a string or a table, for entries of the config which can have extra options.
+///
+/// Unlike `#[serde(untagged)]`, mistakes in the table such as unknown keys are reported
+/// instead of "data did not match any variant"
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::types::Configuration;
+///
+/// let config = r#"
+/// repo = "helix-editor/helix"
+/// local-branch = "patchy"
+/// pull-requests = [{ id = "12111", up_to = "abc1234" }]
+/// "#;
+///
+/// let err = toml::from_str::<Configuration>(config).unwrap_err().to_string();
+///
+/// assert!(err.contains(r#"pull-requests = [{ id = "12111", up_to = "abc1234" }]"#));
+/// assert!(err.contains("unknown field `up_to`, expected one of `id`, `up-to`, `depth`, `use-merge-commit`"));
+/// ```
+pub enum StringOrTable<T> {
+    String(String),
+    Table(T),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for StringOrTable<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringOrTableVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for StringOrTableVisitor<T> {
+            type Value = StringOrTable<T>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a string or a table")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(StringOrTable::String(value.to_owned()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                T::deserialize(MapAccessDeserializer::new(map)).map(StringOrTable::Table)
+            }
+        }
+
+        deserializer.deserialize_any(StringOrTableVisitor(PhantomData))
+    }
+}
+
+/// A pull request from the config, either its number or a table with extra options
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "StringOrTable<DetailedPullRequest>")]
+pub enum PullRequest {
+    /// The number of the pull request, or `<number> @ <commit-hash>`
+    Number(String),
+    Detailed(DetailedPullRequest),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DetailedPullRequest {
+    pub id: String,
+    /// Only merge the commits of the pull request up to and including this one
+    pub up_to: Option<String>,
+    /// How many commits of the pull request's history to fetch, instead of `--depth`
+    pub depth: Option<u32>,
+    /// Once the pull request is merged upstream, apply the commit which merged it instead of its branch
+    #[serde(default)]
+    pub use_merge_commit: bool,
+}
+
+impl From<StringOrTable<DetailedPullRequest>> for PullRequest {
+    fn from(pull_request: StringOrTable<DetailedPullRequest>) -> Self {
+        match pull_request {
+            StringOrTable::String(number) => PullRequest::Number(number),
+            StringOrTable::Table(pull_request) => PullRequest::Detailed(pull_request),
+        }
+    }
+}
+
+/// A pull request of the run, which is fetched and merged in its turn
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedPullRequest {
+    pub number: String,
+    /// The commit from `<number> @ <commit-hash>`
+    pub commit_hash: Option<String>,
+    pub up_to: Option<String>,
+    /// `None` fetches the whole history
+    pub depth: Option<u32>,
+    #[serde(default)]
+    pub use_merge_commit: bool,
+}
+
+impl PullRequest {
+    /// The number of the pull request, and the commit that it is pinned to with `<number> @ <commit-hash>`
+    pub fn number_and_commit(&self) -> (String, Option<String>) {
+        match self {
+            PullRequest::Number(pull_request) => {
+                parse_if_maybe_hash(&ignore_octothorpe(pull_request), " @ ")
+            }
+            PullRequest::Detailed(pull_request) => (ignore_octothorpe(&pull_request.id), None),
+        }
+    }
+
+    pub fn up_to(&self) -> Option<&str> {
+        match self {
+            PullRequest::Number(_) => None,
+            PullRequest::Detailed(pull_request) => pull_request.up_to.as_deref(),
+        }
+    }
+
+    pub fn depth(&self) -> Option<u32> {
+        match self {
+            PullRequest::Number(_) => None,
+            PullRequest::Detailed(pull_request) => pull_request.depth,
+        }
+    }
+
+    pub fn use_merge_commit(&self) -> bool {
+        match self {
+            PullRequest::Number(_) => false,
+            PullRequest::Detailed(pull_request) => pull_request.use_merge_commit,
+        }
+    }
+}
+
+/// A patch from the config, either just its name or a table with extra options
+#[derive(Deserialize, Debug)]
+#[serde(from = "StringOrTable<DetailedPatch>")]
+pub enum Patch {
+    Name(String),
+    Detailed(DetailedPatch),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DetailedPatch {
+    pub name: String,
+    pub applies_if: Option<PatchCondition>,
+}
+
+/// When set, the patch is only applied if every specified condition holds against the checked-out base
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PatchCondition {
+    /// The tag must be an ancestor of the base, i.e. upstream is at least at this version
+    pub min_tag: Option<String>,
+    /// The file must exist in the base
+    pub file: Option<String>,
+    /// ...and contain this text
+    pub contains: Option<String>,
+}
+
+impl From<StringOrTable<DetailedPatch>> for Patch {
+    fn from(patch: StringOrTable<DetailedPatch>) -> Self {
+        match patch {
+            StringOrTable::String(name) => Patch::Name(name),
+            StringOrTable::Table(patch) => Patch::Detailed(patch),
+        }
+    }
+}
+
+impl Patch {
+    pub fn name(&self) -> &str {
+        match self {
+            Patch::Name(name) => name,
+            Patch::Detailed(patch) => &patch.name,
+        }
+    }
+
+    pub fn applies_if(&self) -> Option<&PatchCondition> {
+        match self {
+            Patch::Name(_) => None,
+            Patch::Detailed(patch) => patch.applies_if.as_ref(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GitHubResponse {
     pub head: Head,
     pub title: String,
     pub html_url: String,
+    /// The description of the pull request, which can be empty
+    #[serde(default)]
+    pub body: Option<String>,
+    /// When the pull request was merged, which is `None` if it isn't
+    #[serde(default)]
+    pub merged_at: Option<String>,
+    /// The commit which merged the pull request. Before it is merged, this is the commit of a test merge
+    #[serde(default)]
+    pub merge_commit_sha: Option<String>,
+    /// How many commits the pull request has
+    #[serde(default)]
+    pub commits: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct RepoResponse {
+    pub default_branch: String,
+}
+
+/// An entry of the list of pull requests returned by the GitHub API
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PullRequestListItem {
+    pub number: u64,
+    /// Either `open` or `closed`, merged pull requests are also closed
+    pub state: String,
+    pub merged_at: Option<String>,
+    #[serde(default)]
+    pub title: String,
+}
+
+impl PullRequestListItem {
+    pub fn state(&self) -> PullRequestState {
+        match (self.state.as_str(), &self.merged_at) {
+            ("open", _) => PullRequestState::Open,
+            (_, Some(_)) => PullRequestState::Merged,
+            _ => PullRequestState::Closed,
+        }
+    }
+}
+
+/// A pull request found by `request_pull_request_list`
+#[derive(Debug, Clone)]
+pub struct ListedPullRequest {
+    pub number: String,
+    pub state: PullRequestState,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GistResponse {
+    pub files: BTreeMap<String, GistFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GistFile {
+    pub filename: String,
+    pub raw_url: String,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Head {
     pub repo: Repo,
     pub r#ref: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Repo {
     pub clone_url: String,
 }
@@ -45,6 +410,34 @@ pub struct Remote {
     pub repository_url: String,
 }
 
+/// How pull requests are turned into commits
+#[derive(Debug, Default)]
+pub struct MergeOptions<'a> {
+    /// Trailers added to the commit message
+    pub trailers: &'a [String],
+    /// Use the dates of the pull request's last commit instead of the current time
+    pub preserve_dates: bool,
+    /// Keep the conflict markers in the working tree if the merge fails, instead of undoing it
+    pub leave_conflicts: bool,
+    /// Add a `Signed-off-by` trailer to the commit
+    pub signoff: bool,
+    /// Pull requests merged before this one, which can be the reason of its conflicts
+    pub merged_before: &'a [MergedPullRequest],
+    /// Apply the commit at the end of the branch with `git cherry-pick`, instead of merging the whole branch
+    pub cherry_pick: Option<CherryPick>,
+}
+
+/// How the commit which merged a pull request upstream is applied, for `use-merge-commit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CherryPick {
+    /// A merge commit, whose changes are the ones it made to the branch that it was merged into
+    Merge,
+    /// A single commit, which is what squash merging creates
+    Commit,
+    /// The last of this many commits, which were rebased onto upstream
+    Rebased { commits: u64 },
+}
+
 #[derive(Debug)]
 pub struct BranchAndRemote {
     pub branch: Branch,
@@ -1,18 +1,33 @@
+use std::{
+    env,
+    io::{IsTerminal, Write},
+    process::{Command, Stdio},
+};
+
 use anyhow::anyhow;
 use rand::Rng;
 use reqwest::{header::USER_AGENT, Client};
+use serde::de::DeserializeOwned;
 
-use crate::types::GitHubResponse;
+use crate::{
+    commands::help::{NO_PAGER_FLAG, PAGER_FLAG},
+    flags::REDACT,
+    redact::{redact, register},
+    types::{CommandArgs, GitHubResponse},
+};
 
 pub fn with_uuid(s: &str) -> String {
-    format!(
+    let name = format!(
         "{uuid}-{s}",
         uuid = rand::thread_rng()
             .sample_iter(&rand::distributions::Alphanumeric)
             .take(4)
             .map(char::from)
             .collect::<String>()
-    )
+    );
+    register("name", &name);
+
+    name
 }
 
 /// Converts a commit message to only contain lowercase characters, underscores and dashes
@@ -31,11 +46,332 @@ pub fn normalize_commit_msg(commit_msg: &str) -> String {
         .collect()
 }
 
+/// Checks that a trailer is of the form `Token: value`, which is what `git interpret-trailers` expects
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::is_valid_trailer;
+///
+/// assert!(is_valid_trailer("Upstream-PR: {repo}#{pull_request}"));
+/// assert!(!is_valid_trailer("Upstream PR: {repo}#{pull_request}"));
+/// assert!(!is_valid_trailer("Upstream-PR:"));
+/// assert!(!is_valid_trailer("Upstream-PR: a\nb"));
+/// ```
+pub fn is_valid_trailer(trailer: &str) -> bool {
+    let Some((token, value)) = trailer.split_once(": ") else {
+        return false;
+    };
+
+    !token.is_empty()
+        && token.chars().all(|ch| ch.is_alphanumeric() || ch == '-')
+        && !value.trim().is_empty()
+        && !value.contains('\n')
+}
+
+/// Git subcommands which only read the repository or do housekeeping on it. They don't change any branch,
+/// but `gc` removes unreachable objects which are older than the grace period of git,
+/// such as commits which were dropped a while ago but could still have been recovered
+static MAINTENANCE_GIT_COMMANDS: &[&str] = &[
+    "gc",
+    "repack",
+    "prune-packed",
+    "pack-refs",
+    "commit-graph",
+    "multi-pack-index",
+    "fsck",
+    "count-objects",
+];
+
+/// Checks that a `pre-fetch-git` command is a read or maintenance git subcommand
+///
+/// `git maintenance` is only allowed with `run`, since its other subcommands change the git config.
+/// Removing unreachable objects right away is not allowed, since then nothing that was recently dropped
+/// could be recovered. That is what e.g. `--prune=now` does, and `repack -a -d` unless it keeps them
+/// with `-A`, `--keep-unreachable` or `--cruft`
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::is_maintenance_git_command;
+///
+/// let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+///
+/// assert!(is_maintenance_git_command(&command(&["gc", "--auto"])));
+/// assert!(is_maintenance_git_command(&command(&["maintenance", "run", "--auto"])));
+/// assert!(!is_maintenance_git_command(&command(&["maintenance", "register"])));
+/// assert!(!is_maintenance_git_command(&command(&["reset", "--hard"])));
+/// assert!(!is_maintenance_git_command(&command(&["-c", "alias.gc=reset --hard", "gc"])));
+/// assert!(!is_maintenance_git_command(&command(&["gc", "--prune=now"])));
+/// assert!(!is_maintenance_git_command(&command(&["prune"])));
+/// assert!(!is_maintenance_git_command(&command(&["repack", "-a", "-d"])));
+/// assert!(!is_maintenance_git_command(&command(&["repack", "-ad"])));
+/// assert!(is_maintenance_git_command(&command(&["repack", "-A", "-d"])));
+/// assert!(is_maintenance_git_command(&command(&["repack", "-ad", "--cruft"])));
+/// assert!(!is_maintenance_git_command(&[]));
+/// ```
+pub fn is_maintenance_git_command(command: &[String]) -> bool {
+    let removes_recent_objects = command.iter().any(|arg| {
+        [
+            "--prune=",
+            "--expire",
+            "--unpack-unreachable=",
+            "--cruft-expiration=",
+        ]
+        .iter()
+        .any(|option| arg.starts_with(option))
+            && (arg.ends_with("=now") || arg.ends_with("=all"))
+    });
+
+    // e.g. `-ad` is `-a -d`
+    let has_short_flag = |flag: char| {
+        command
+            .iter()
+            .any(|arg| !arg.starts_with("--") && arg.starts_with('-') && arg.contains(flag))
+    };
+    let drops_unreachable_packs = command
+        .first()
+        .is_some_and(|subcommand| subcommand == "repack")
+        && has_short_flag('a')
+        && !has_short_flag('A')
+        && !command
+            .iter()
+            .any(|arg| arg == "--keep-unreachable" || arg == "--cruft");
+
+    if removes_recent_objects || drops_unreachable_packs {
+        return false;
+    }
+
+    match command {
+        [subcommand, ..] if MAINTENANCE_GIT_COMMANDS.contains(&subcommand.as_str()) => true,
+        [subcommand, action, ..] => subcommand == "maintenance" && action == "run",
+        _ => false,
+    }
+}
+
+/// Fills in the placeholders of a `commit-trailers` template with information about a pull request
+pub fn render_trailer(
+    template: &str,
+    repo: &str,
+    pull_request: &str,
+    response: &GitHubResponse,
+) -> String {
+    template
+        .replace("{repo}", repo)
+        .replace("{pull_request}", pull_request)
+        .replace("{title}", &response.title.replace('\n', " "))
+        .replace("{url}", &response.html_url)
+        .replace("{branch}", &response.head.r#ref)
+}
+
+/// Formats seconds since the UNIX epoch as a UTC date and time
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::format_timestamp;
+///
+/// assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+/// assert_eq!(format_timestamp(1734567890), "2024-12-19 00:24:50 UTC");
+/// ```
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds_of_day = timestamp % 86400;
+
+    // converts days since the epoch into a civil date, see https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Whether the environment variable probably holds a secret, so its value should not be logged
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::is_secret_name;
+///
+/// assert!(is_secret_name("GITHUB_TOKEN"));
+/// assert!(is_secret_name("aws_secret_access_key"));
+/// assert!(is_secret_name("SSH_KEY"));
+/// assert!(!is_secret_name("CARGO_NET_OFFLINE"));
+/// assert!(!is_secret_name("GIT_AUTHOR_NAME"));
+/// ```
+pub fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+
+    ["TOKEN", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL"]
+        .iter()
+        .any(|pattern| name.contains(pattern))
+        // these are too short to search for anywhere in the name, e.g. `GIT_AUTHOR_NAME` is fine
+        || name
+            .split('_')
+            .any(|word| matches!(word, "KEY" | "AUTH" | "PAT"))
+}
+
+/// Removes the escape sequences which terminals use for colors and links, keeping the text of links
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::{display_link, strip_ansi};
+///
+/// assert_eq!(strip_ansi("\u{1b}[1;94mbold\u{1b}[0m text"), "bold text");
+/// assert_eq!(strip_ansi(&format!("see {}", display_link("docs", "https://example.com"))), "see docs");
+/// ```
+pub fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\u{1b}' {
+            stripped.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            // links and titles end with `ESC \` or BEL
+            Some(']') => {
+                while let Some(ch) = chars.next() {
+                    if ch == '\u{7}' || ch == '\u{1b}' && chars.next_if_eq(&'\\').is_some() {
+                        break;
+                    }
+                }
+            }
+            // colors end with a letter, e.g. `ESC [94m`
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ch.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    stripped
+}
+
 pub fn display_link(text: &str, url: &str) -> String {
+    // the link is not visible, but it is still in the output
+    if *REDACT {
+        return text.to_owned();
+    }
+
     format!("\u{1b}]8;;{}\u{1b}\\{}\u{1b}]8;;\u{1b}\\", url, text)
 }
 
-pub async fn make_request(client: &Client, url: &str) -> anyhow::Result<GitHubResponse> {
+pub async fn make_request<T: DeserializeOwned>(client: &Client, url: &str) -> anyhow::Result<T> {
+    let out = download_text(client, url).await?;
+
+    serde_json::from_str(&out).map_err(|err| {
+        anyhow!("Could not parse response.\n{out}. Could not parse because: \n{err}")
+    })
+}
+
+/// Checks whether the text looks like something that `git am` or `git apply` can use
+///
+/// # Examples
+///
+/// ```rust
+/// use patchy::utils::is_patch;
+///
+/// assert!(is_patch("diff --git a/README.md b/README.md\n--- a/README.md\n+++ b/README.md\n@@ -1 +1 @@\n-a\n+b\n"));
+/// assert!(is_patch("--- a/README.md\n+++ b/README.md\n@@ -1 +1 @@\n-a\n+b\n"));
+/// assert!(!is_patch("fn main() {}"));
+/// ```
+pub fn is_patch(text: &str) -> bool {
+    let has_line = |prefix: &str| text.lines().any(|line| line.starts_with(prefix));
+
+    has_line("--- ") && has_line("+++ ") && has_line("@@ ")
+}
+
+/// Patches created by `git format-patch` are in the mbox format and can be applied with `git am`, keeping the commit message
+pub fn is_mbox_patch(text: &str) -> bool {
+    text.starts_with("From ")
+}
+
+/// Shows long output through `$PAGER` (`less -R` by default) like git does, if stdout is a terminal.
+///
+/// `--pager=always` pages even when stdout isn't a terminal, `--pager=never` or `--no-pager` disable it
+pub fn page(output: &str, args: &CommandArgs) -> anyhow::Result<()> {
+    let output = &redact(output);
+
+    let pager_mode = if NO_PAGER_FLAG.is_in_args(args) {
+        "never".to_owned()
+    } else {
+        args.iter()
+            .find_map(|arg| PAGER_FLAG.extract_from_arg(arg))
+            .unwrap_or("auto".to_owned())
+    };
+
+    let use_pager = match pager_mode.as_str() {
+        "auto" => std::io::stdout().is_terminal(),
+        "always" => true,
+        "never" => false,
+        _ => {
+            return Err(anyhow!(
+                "Invalid flag {}{pager_mode}, expected one of: auto, always, never",
+                PAGER_FLAG.long
+            ))
+        }
+    };
+
+    let pager = env::var("PAGER").unwrap_or("less -R".to_owned());
+    let mut pager_args = pager.split_whitespace();
+
+    let Some(program) = pager_args
+        .next()
+        .filter(|program| use_pager && *program != "cat")
+    else {
+        print!("{output}");
+        return Ok(());
+    };
+
+    let mut command = Command::new(program);
+    command.args(pager_args).stdin(Stdio::piped());
+
+    // the same defaults as git: quit if the output fits on one screen, keep colors and don't clear the screen
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+
+    let Ok(mut child) = command.spawn() else {
+        // no pager is better than no output
+        print!("{output}");
+        return Ok(());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // the user can quit the pager before reading all of the output
+        let _ = stdin.write_all(output.as_bytes());
+    }
+
+    child.wait()?;
+
+    Ok(())
+}
+
+pub async fn download_text(client: &Client, url: &str) -> anyhow::Result<String> {
     let request = client
         .get(url)
         .header(USER_AGENT, "{APP_NAME}")
@@ -43,15 +379,7 @@ pub async fn make_request(client: &Client, url: &str) -> anyhow::Result<GitHubRe
         .await;
 
     match request {
-        Ok(res) if res.status().is_success() => {
-            let out = res.text().await?;
-
-            let response: GitHubResponse = serde_json::from_str(&out).map_err(|err| {
-                anyhow!("Could not parse response.\n{out}. Could not parse because: \n{err}")
-            })?;
-
-            Ok(response)
-        }
+        Ok(res) if res.status().is_success() => Ok(res.text().await?),
         Ok(res) => {
             let status = res.status();
             let text = res.text().await?;
@@ -68,10 +396,17 @@ pub async fn make_request(client: &Client, url: &str) -> anyhow::Result<GitHubRe
 #[macro_export]
 macro_rules! success {
     ($($arg:tt)*) => {{
-        println!("{}{}{}",
+        let message = format!("{}{}{}",
             $crate::INDENT,
             colored::Colorize::bold(colored::Colorize::bright_green("✓ ")),
-            format!($($arg)*))
+            $crate::redact::redact(&format!($($arg)*)));
+
+        // stdout is reserved for the events
+        if *$crate::flags::EMIT_EVENTS {
+            eprintln!("{message}")
+        } else {
+            println!("{message}")
+        }
     }};
 }
 
@@ -81,7 +416,7 @@ macro_rules! fail {
         eprintln!("{}{}{}",
             $crate::INDENT,
             colored::Colorize::bold(colored::Colorize::bright_red("✗ ")),
-            format!($($arg)*))
+            $crate::redact::redact(&format!($($arg)*)))
     }};
 }
 
@@ -92,7 +427,7 @@ macro_rules! trace {
             eprintln!("{}{}{}",
                 $crate::INDENT,
                 colored::Colorize::bold(colored::Colorize::bright_yellow("--verbose: ")),
-                format!($($arg)*))
+                $crate::redact::redact(&format!($($arg)*)))
         }
     }};
 }
@@ -103,7 +438,7 @@ macro_rules! info {
         eprintln!("{}{}{}",
             $crate::INDENT,
             colored::Colorize::bright_blue(colored::Colorize::bold("i ")),
-            format!($($arg)*))
+            $crate::redact::redact(&format!($($arg)*)))
     }};
 }
 
@@ -115,7 +450,7 @@ macro_rules! confirm_prompt {
             .with_prompt(format!(
                 "\n{INDENT}{} {}",
                 "»".bright_black(),
-                format!($($arg)*)
+                $crate::redact::redact(&format!($($arg)*))
             ))
             .interact()
             .unwrap()